@@ -11,14 +11,21 @@ use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::Mutex;
 
-use delenix_lib::{config::Config, screenshot::ScreenshotType, upload};
+use delenix_lib::{
+    config::Config,
+    expiry,
+    screenshot::{ScreenshotType, Session, WindowInfo},
+    upload::{self, UploadOptions, UploadResult},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
     SetConfig(SetConfig),
     GetConfig(GetConfig),
     Upload(Upload),
-    Screenshot(ScreenshotType),
+    Screenshot(ScreenshotType), // Answered with just the PNG
+    ScreenshotUpload(ScreenshotUpload),
+    ListWindows(ListWindows),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,6 +40,26 @@ pub struct GetConfig;
 pub struct Upload {
     pub data: Vec<u8>,
     pub format: String,
+    #[serde(default)]
+    pub options: UploadOptions,
+}
+
+// Takes a screenshot and uploads it, answered with a ScreenshotResponse
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScreenshotUpload {
+    pub screenshot_type: ScreenshotType,
+    #[serde(default)]
+    pub options: UploadOptions,
+}
+
+// Answered with the windows as a list of WindowInfo, bottom to top
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListWindows;
+
+#[derive(Serialize, Deserialize)]
+pub struct ScreenshotResponse {
+    pub data: Vec<u8>,
+    pub results: Vec<UploadResult>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -60,7 +87,34 @@ impl ErrorResponse {
     }
 }
 
+// How often the daemon looks for expired uploads to delete
+const EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub async fn start_ipc(conf: Arc<Mutex<Config>>) {
+    let expiry_conf = Arc::clone(&conf);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let conf = expiry_conf.lock().await.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || expiry::run_due(&conf)).await {
+                tracing::error!("Failed to delete expired uploads: {}", e);
+            }
+        }
+    });
+
+    // one connection to the X server for every capture, rather than one each. Without a display the daemon still
+    // serves uploads, and captures try to connect on their own.
+    let session = match conf.lock().await.session() {
+        Ok(session) => Some(Arc::new(session)),
+        Err(e) => {
+            tracing::warn!("{}", e);
+            None
+        }
+    };
+
     let server_task = tokio::spawn(async move {
         // Server code
         #[cfg(target_os = "windows")]
@@ -89,8 +143,9 @@ pub async fn start_ipc(conf: Arc<Mutex<Config>>) {
                 let localaddr = handle_error!(stream.local_addr());
                 tracing::info!("Got a connection from {:?}", localaddr);
                 let conf = Arc::clone(&conf);
+                let session = session.clone();
                 tokio::spawn(async move {
-                    handle_client(&conf, Box::pin(stream)).await;
+                    handle_client(&conf, session, Box::pin(stream)).await;
                 });
             }
         }
@@ -123,7 +178,34 @@ trait AsyncRW: AsyncRead + AsyncWrite + Send {}
 
 impl<T> AsyncRW for T where T: AsyncRead + AsyncWrite + Send {}
 
-async fn handle_client(config: &Mutex<Config>, mut stream: Pin<Box<dyn AsyncRW + Send>>) {
+// Captures through the daemon's session if it has one
+fn capture(
+    config: &Config,
+    session: Option<&Session>,
+    typ: ScreenshotType,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    match session {
+        Some(session) => config.screenshot_with(session, typ),
+        None => config.screenshot(typ),
+    }
+}
+
+// Runs X calls, uploads and hooks off the async workers, answering with the result or an ErrorResponse
+async fn respond_blocking<T: serde::Serialize + Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Vec<u8> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(Ok(response)) => serde_json::to_vec(&response).unwrap(),
+        Ok(Err(err)) => serde_json::to_vec(&ErrorResponse::new(err)).unwrap(),
+        Err(err) => serde_json::to_vec(&ErrorResponse::new(err.to_string())).unwrap(),
+    }
+}
+
+async fn handle_client(
+    config: &Mutex<Config>,
+    session: Option<Arc<Session>>,
+    mut stream: Pin<Box<dyn AsyncRW + Send>>,
+) {
     let mut buffer = [0; 1024];
     loop {
         let bytes_read = handle_error!(stream.read(&mut buffer).await);
@@ -146,21 +228,50 @@ async fn handle_client(config: &Mutex<Config>, mut stream: Pin<Box<dyn AsyncRW +
                 stream.write_all(&response).await.unwrap();
             }
             Request::Upload(upload) => {
-                let config: tokio::sync::MutexGuard<'_, Config> = config.lock().await;
-                let results = upload::upload(&*config, &upload.data, &upload.format).unwrap();
-                let response = serde_json::to_vec(&results).unwrap();
+                let config = config.lock().await.clone();
+                let response = respond_blocking(move || {
+                    upload::upload(&config, &upload.data, &upload.format, &upload.options)
+                        .map_err(|err| err.to_string())
+                })
+                .await;
                 stream.write_all(&response).await.unwrap();
             }
             Request::Screenshot(screenshot_type) => {
-                let config = config.lock().await;
-                let results = match config.screenshot(screenshot_type) {
-                    Ok(results) => results,
-                    Err(err) => {
-                        let err = ErrorResponse::new(err.to_string());
-                        serde_json::to_vec(&err).unwrap()
-                    }
-                };
-                let response = serde_json::to_vec(&results).unwrap();
+                let config = config.lock().await.clone();
+                let session = session.clone();
+                let response = respond_blocking(move || {
+                    capture(&config, session.as_deref(), screenshot_type)
+                        .map_err(|err| err.to_string())
+                })
+                .await;
+                stream.write_all(&response).await.unwrap();
+            }
+            Request::ScreenshotUpload(screenshot) => {
+                let config = config.lock().await.clone();
+                let session = session.clone();
+                let response = respond_blocking(move || {
+                    capture(&config, session.as_deref(), screenshot.screenshot_type)
+                        .and_then(|data| {
+                            let results =
+                                upload::upload(&config, &data, "png", &screenshot.options)?;
+                            Ok(ScreenshotResponse { data, results })
+                        })
+                        .map_err(|err| err.to_string())
+                })
+                .await;
+                stream.write_all(&response).await.unwrap();
+            }
+            Request::ListWindows(_list_windows) => {
+                let config = config.lock().await.clone();
+                let session = session.clone();
+                let response = respond_blocking(move || {
+                    let windows: Result<Vec<WindowInfo>, _> = match session {
+                        Some(session) => session.list_windows(),
+                        None => config.session().and_then(|session| session.list_windows()),
+                    };
+                    windows.map_err(|err| err.to_string())
+                })
+                .await;
                 stream.write_all(&response).await.unwrap();
             }
         }
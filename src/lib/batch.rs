@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, Uploader},
+    upload::{self, UploadOptions, UploadResult},
+};
+
+// How many files are uploaded at the same time
+const MAX_CONCURRENT_UPLOADS: usize = 4;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileResult {
+    pub path: String,
+    pub results: Vec<UploadResult>,
+    pub error_message: Option<String>, // Set if the file couldn't be read or uploaded at all
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub albums: Vec<UploadResult>, // One per uploader that grouped the files into an album
+    pub files: Vec<FileResult>,    // In the same order as the paths that were given
+    #[serde(default)]
+    pub webhooks: Vec<UploadResult>, // Sent once for the whole batch, announcing the album if there is one
+}
+
+impl BatchResult {
+    pub fn failed(&self) -> bool {
+        let failed = |r: &UploadResult| r.error_message.is_some();
+
+        self.albums.iter().any(failed)
+            || self.webhooks.iter().any(failed)
+            || self
+                .files
+                .iter()
+                .any(|f| f.error_message.is_some() || f.results.iter().any(failed))
+    }
+}
+
+// Turns the given arguments into a list of files, directories are expanded to the files directly inside them
+// and anything containing a glob pattern is expanded to the files it matches
+pub fn expand_paths(args: &[String]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut paths = Vec::new();
+
+    for arg in args {
+        let path = Path::new(arg);
+
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file())
+                .collect();
+            entries.sort();
+
+            paths.extend(entries);
+        } else if arg.contains(['*', '?', '[']) {
+            for entry in glob::glob(arg)? {
+                let entry = entry?;
+                if entry.is_file() {
+                    paths.push(entry);
+                }
+            }
+        } else {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    Ok(paths)
+}
+
+// Uploads every file concurrently, then creates an album for each selected uploader that supports them, and
+// finally announces the batch on the webhooks once, with the album's link if there is one
+pub fn upload(conf: &Config, paths: &[PathBuf], opts: &UploadOptions) -> BatchResult {
+    let file_opts = UploadOptions {
+        skip_webhooks: true,
+        ..opts.clone()
+    };
+
+    let next = AtomicUsize::new(0);
+    let files: Mutex<Vec<Option<FileResult>>> = Mutex::new(vec![None; paths.len()]);
+
+    std::thread::scope(|s| {
+        for _ in 0..MAX_CONCURRENT_UPLOADS.min(paths.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let path = match paths.get(i) {
+                    Some(path) => path,
+                    None => break,
+                };
+
+                let result = upload_file(conf, path, &file_opts);
+                files.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    let files: Vec<FileResult> = files.into_inner().unwrap().into_iter().flatten().collect();
+
+    let mut albums = Vec::new();
+
+    if files.len() > 1 {
+        for uploader in conf.uploaders.iter().filter(|u| opts.selection.includes(u)) {
+            let u = match uploader {
+                Uploader::HTTP(u) if u.album.is_some() => u,
+                _ => continue,
+            };
+
+            let items: Vec<String> = files
+                .iter()
+                .flat_map(|f| &f.results)
+                .filter(|r| r.uploader_name == u.name && r.error_message.is_none())
+                .filter_map(|r| r.album_item.clone())
+                .collect();
+
+            if items.len() < 2 {
+                continue;
+            }
+
+            tracing::info!("Creating album on {} with {} files", u.name, items.len());
+
+            albums.push(match upload::create_album(u, &items) {
+                Ok(result) => result,
+                Err(e) => UploadResult::error(&u.name, e.to_string()),
+            });
+        }
+    }
+
+    let webhooks = if opts.skip_webhooks {
+        Vec::new()
+    } else {
+        let webhooks: Vec<&Uploader> = conf
+            .uploaders
+            .iter()
+            .filter(|u| opts.selection.includes(u) && u.is_webhook())
+            .collect();
+
+        // no single file to attach, the webhooks only get the link
+        let results: Vec<UploadResult> = albums
+            .iter()
+            .chain(files.iter().flat_map(|f| &f.results))
+            .cloned()
+            .collect();
+        let filename = paths
+            .first()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        upload::notify_webhooks(conf, &webhooks, None, &results, &filename, opts)
+    };
+
+    BatchResult {
+        albums,
+        files,
+        webhooks,
+    }
+}
+
+fn upload_file(conf: &Config, path: &Path, opts: &UploadOptions) -> FileResult {
+    let format = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("png")
+        .to_lowercase();
+
+    let opts = UploadOptions {
+        filename: path.file_name().map(|n| n.to_string_lossy().into_owned()),
+        ..opts.clone()
+    };
+
+    let result = std::fs::read(path)
+        .map_err(|e| e.into())
+        .and_then(|data| upload::upload(conf, &data, &format, &opts));
+
+    let (results, error_message) = match result {
+        Ok(results) => (results, None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+
+    FileResult {
+        path: path.to_string_lossy().into_owned(),
+        results,
+        error_message,
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{Config, Uploader},
+    upload::UploadResult,
+    util::{self, make_default_cache_path},
+};
+
+// Remembers what was uploaded where, keyed by the SHA-256 of the file, so uploading the same bytes to the same
+// uploader again hands back the earlier result instead. Only successful uploads with a URL are kept, and only
+// while the uploader's settings stay the same.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub path: Option<String>, // Defaults to ~/.cache/delenix/uploads.json
+    pub max_age: Option<u64>, // Seconds after which an entry is dropped, hosts may delete old uploads
+    pub max_entries: Option<usize>, // The oldest entries are dropped past this
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            max_age: Some(30 * 24 * 60 * 60),
+            max_entries: Some(1000),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub uploader_name: String,
+    pub hash: String,
+    #[serde(default)]
+    pub settings: String, // Hash of the uploader's config at the time, see settings
+    pub uploaded_at: i64, // Unix timestamp
+    pub result: UploadResult,
+}
+
+lazy_static! {
+    // batch uploads run several uploads at once, which would otherwise overwrite each other's entries
+    static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+pub fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Hashes the whole uploader config, so e.g. turning on encrypt or pointing it at another host uploads again.
+// The config's HashMaps serialize in a different order every run, so the keys are sorted before hashing.
+fn settings(uploader: &Uploader) -> String {
+    let value = sorted(serde_json::to_value(uploader).unwrap_or_default());
+    hash(value.to_string().as_bytes())
+}
+
+// serde_json keeps maps in insertion order here, so rebuilding them from sorted entries sorts them
+fn sorted(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            entries
+                .into_iter()
+                .map(|(k, v)| (k, sorted(v)))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }
+        serde_json::Value::Array(values) => values.into_iter().map(sorted).collect(),
+        value => value,
+    }
+}
+
+// Finds an earlier upload of the same file to the uploader, if it hasn't expired yet
+pub fn lookup(conf: &Config, uploader: &Uploader, hash: &str) -> Option<UploadResult> {
+    if !conf.cache.enabled {
+        return None;
+    }
+
+    let _lock = LOCK.lock().unwrap();
+
+    let now = chrono::Utc::now().timestamp();
+    let settings = settings(uploader);
+
+    load(conf)
+        .into_iter()
+        .filter(|e| !expired(conf, e, now))
+        .find(|e| {
+            e.hash == hash
+                && e.settings == settings
+                && e.uploader_name.eq_ignore_ascii_case(uploader.name())
+        })
+        .map(|e| e.result)
+}
+
+pub fn store(conf: &Config, uploader: &Uploader, hash: &str, result: &UploadResult) {
+    if !conf.cache.enabled || result.error_message.is_some() || result.url.is_none() {
+        return;
+    }
+
+    update(conf, |entries| {
+        entries.retain(|e| {
+            !(e.hash == hash && e.uploader_name.eq_ignore_ascii_case(&result.uploader_name))
+        });
+        entries.push(CacheEntry {
+            uploader_name: result.uploader_name.clone(),
+            hash: hash.to_string(),
+            settings: settings(uploader),
+            uploaded_at: chrono::Utc::now().timestamp(),
+            result: result.clone(),
+        });
+    });
+}
+
+// Forgets an upload, e.g. once it has been deleted from the host
+pub fn remove(conf: &Config, uploader_name: &str, url: &str) {
+    if !conf.cache.enabled {
+        return;
+    }
+
+    update(conf, |entries| {
+        entries.retain(|e| {
+            !(e.uploader_name.eq_ignore_ascii_case(uploader_name)
+                && e.result.url.as_deref() == Some(url))
+        });
+    });
+}
+
+// Loads the cache, applies the change and the eviction policy, and writes it back
+fn update(conf: &Config, f: impl FnOnce(&mut Vec<CacheEntry>)) {
+    let _lock = LOCK.lock().unwrap();
+
+    let mut entries = load(conf);
+    f(&mut entries);
+
+    let now = chrono::Utc::now().timestamp();
+    entries.retain(|e| !expired(conf, e, now));
+
+    if let Some(max_entries) = conf.cache.max_entries {
+        entries.sort_by_key(|e| e.uploaded_at);
+        let excess = entries.len().saturating_sub(max_entries);
+        entries.drain(..excess);
+    }
+
+    if let Err(e) = save(conf, &entries) {
+        tracing::warn!("Failed to save the upload cache: {}", e);
+    }
+}
+
+fn expired(conf: &Config, entry: &CacheEntry, now: i64) -> bool {
+    conf.cache
+        .max_age
+        .is_some_and(|max_age| now - entry.uploaded_at > max_age as i64)
+}
+
+fn path(conf: &Config) -> String {
+    conf.cache
+        .path
+        .clone()
+        .unwrap_or_else(make_default_cache_path)
+}
+
+// A missing or corrupt cache is treated as empty, it only saves bandwidth after all
+fn load(conf: &Config) -> Vec<CacheEntry> {
+    let s = match std::fs::read_to_string(path(conf)) {
+        Ok(s) => s,
+        Err(_) => return Vec::new(),
+    };
+
+    serde_json::from_str(&s).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring the corrupt upload cache: {}", e);
+        Vec::new()
+    })
+}
+
+fn save(conf: &Config, entries: &[CacheEntry]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = path(conf);
+
+    if let Some(parent) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    util::write_atomic(&path, serde_json::to_string(entries)?.as_bytes())?;
+
+    Ok(())
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use serde_derive::{Deserialize, Serialize};
 
-use crate::{screenshot, util::make_default_image_path};
+use crate::{cache::CacheConfig, hooks::Hooks, screenshot, util::make_default_image_path};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -15,16 +15,39 @@ pub struct Config {
     #[serde(default)]
     pub copy_url_to_clipboard: bool,
     #[serde(default)]
+    pub clipboard_template: Option<String>, // What's copied instead of the bare URL, see util::clipboard_text
+    #[serde(default)]
+    pub primary_uploader: Option<String>, // Whose link is copied when several uploaders succeed, the last if None
+    #[serde(default)]
     pub freeze_screen: bool,
     #[serde(default)]
+    pub selection_monitor: Option<screenshot::MonitorSelector>, // Keep region selection to one monitor, e.g. Current
+    #[serde(default)]
+    pub display: Option<String>, // The X display to capture, e.g. :99 for an Xvfb session, $DISPLAY if None
+    #[serde(default)]
+    pub screen: Option<usize>, // The screen of that display, the display's default if None
+    #[serde(default)]
+    pub exclude_window_frame: bool, // Capture windows without the window manager's decorations
+    #[serde(default)]
+    pub show_cursor: bool, // Draw the mouse cursor into captures
+    #[serde(default)]
     pub show_notification: bool,
     pub tessdata_path: Option<String>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub viewer_url: Option<String>, // Where viewer/index.html is hosted, links to encrypted uploads open it if set
+    #[serde(default)]
+    pub cache: CacheConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Uploader {
     HTTP(HttpUploader),
     File(FileUploader),
+    Command(CommandUploader),
+    Script(ScriptUploader),
+    Email(EmailUploader),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -35,6 +58,7 @@ pub enum DestinationType {
     FileUploader,
     URLShortener,
     URLSharingService,
+    Webhook, // Not an uploader, posts a message with the result of the other uploaders once they're done
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -62,6 +86,32 @@ pub struct HttpUploader {
     pub thumbnail_url: Option<String>,
     pub deletion_url: Option<String>,
     pub error_message: Option<String>,
+    #[serde(default)]
+    pub response_script: Option<String>, // Path to a script that parses the response instead of the fields above
+    #[serde(default)]
+    pub album: Option<Album>, // How to group several uploads into one album, for hosts that support it
+    #[serde(default)]
+    pub payload: Option<String>, // JSON template for Webhook destinations, see upload::notify_webhook
+    #[serde(default)]
+    pub encrypt: bool, // Encrypt the file before sending it, the key is only kept in the returned URL, see crypto.rs
+    #[serde(default)]
+    pub expire: Option<String>, // Delete uploads after this long, e.g. 24h, see expiry.rs
+    #[serde(default)]
+    pub deletion_method: Option<String>, // Method used to request the deletion URL, defaults to GET
+    #[serde(default)]
+    pub clipboard_template: Option<String>, // Overrides the global clipboard_template for this uploader
+}
+
+// The request made after a batch upload to group the uploaded files, it is sent as a form with one
+// item_form_name field per upload, along with the uploader's headers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Album {
+    pub request_method: String,
+    pub request_url: String,
+    pub item: String, // Custom syntax picking the id of each upload out of its response
+    pub item_form_name: String,
+    pub url: String, // Custom syntax for the URL of the album, applied to the album response
+    pub deletion_url: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -69,9 +119,98 @@ pub struct FileUploader {
     pub name: String,      // Name of the uploader
     pub file_path: String, // Path the file will be saved to
     pub file_name: String, // Name of the file (without extension)
+    #[serde(default)]
+    pub expire: Option<String>, // Delete saved files after this long, e.g. 7d
+}
+
+// Runs an external program to upload the file, see upload::upload_command for the protocol
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandUploader {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub timeout: Option<u64>, // Seconds to wait for the program before killing it, waits forever if None
+    #[serde(default)]
+    pub clipboard_template: Option<String>,
+}
+
+// Runs a script to upload the file, for APIs the HTTP uploader can't describe, see script::upload for the API
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptUploader {
+    pub name: String,
+    pub path: String, // Path to the script
+    #[serde(default)]
+    pub clipboard_template: Option<String>,
+}
+
+// Mails the file to a list of recipients, the subject and body are templates like file names,
+// with $filename$ and $window_title$ on top, see email::send
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmailUploader {
+    pub name: String,
+    pub host: String,
+    pub port: Option<u16>, // Defaults to 465 for Tls and 587 for StartTls
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub inline: bool, // Embed the image in an HTML body instead of attaching it
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum SmtpSecurity {
+    None, // Plain text, only for local relays
+    StartTls,
+    #[default]
+    Tls,
 }
 
 impl Uploader {
+    pub fn is_webhook(&self) -> bool {
+        matches!(self, Self::HTTP(u) if matches!(u.destination_type, DestinationType::Webhook))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::HTTP(u) => &u.name,
+            Self::File(u) => &u.name,
+            Self::Command(u) => &u.name,
+            Self::Script(u) => &u.name,
+            Self::Email(u) => &u.name,
+        }
+    }
+
+    // Only uploaders that return a URL can have one
+    pub fn clipboard_template(&self) -> Option<&str> {
+        match self {
+            Self::HTTP(u) => u.clipboard_template.as_deref(),
+            Self::Command(u) => u.clipboard_template.as_deref(),
+            Self::Script(u) => u.clipboard_template.as_deref(),
+            _ => None,
+        }
+    }
+
+    // The time to live set in the config, uploaders that can't delete their uploads have none
+    pub fn expire(&self) -> Option<&str> {
+        match self {
+            Self::HTTP(u) => u.expire.as_deref(),
+            Self::File(u) => u.expire.as_deref(),
+            _ => None,
+        }
+    }
+
+    // Whether uploads to it can be deleted again, mail can't be unsent and programs have no way to delete
+    pub fn can_expire(&self) -> bool {
+        matches!(self, Self::HTTP(_) | Self::File(_))
+    }
+
     pub fn from_sharex(c: sharex::Config) -> Self {
         Self::HTTP(HttpUploader {
             name: c.name,
@@ -87,11 +226,46 @@ impl Uploader {
             thumbnail_url: Some(c.thumbnail_url),
             deletion_url: Some(c.deletion_url),
             error_message: Some(c.error_message),
+            response_script: None,
+            album: None,
+            payload: None,
+            encrypt: false,
+            expire: None,
+            deletion_method: None,
+            clipboard_template: None,
         })
     }
 }
 
+impl HttpUploader {
+    // The ShareX custom uploader (.sxcu) equivalent, the reverse of Uploader::from_sharex
+    pub fn to_sharex(&self) -> sharex::Config {
+        sharex::Config {
+            name: self.name.clone(),
+            destination_type: self.destination_type.clone(),
+            request_method: self.request_method.clone(),
+            request_url: self.request_url.clone(),
+            parameters: self.parameters.clone().unwrap_or_default(),
+            headers: self.headers.clone().unwrap_or_default(),
+            body: self.body.clone(),
+            arguments: self.arguments.clone().unwrap_or_default(),
+            file_form_name: self.file_form_name.clone().unwrap_or_default(),
+            url: self.url.clone(),
+            thumbnail_url: self.thumbnail_url.clone().unwrap_or_default(),
+            deletion_url: self.deletion_url.clone().unwrap_or_default(),
+            error_message: self.error_message.clone().unwrap_or_default(),
+        }
+    }
+}
+
 impl Config {
+    // Finds an uploader by its name, names are matched case-insensitively
+    pub fn uploader(&self, name: &str) -> Option<&Uploader> {
+        self.uploaders
+            .iter()
+            .find(|u| u.name().eq_ignore_ascii_case(name))
+    }
+
     pub fn from_file(path: String) -> Result<Self, Box<dyn std::error::Error>> {
         let s = match std::fs::read_to_string(&path) {
             Ok(s) => s,
@@ -157,6 +331,7 @@ impl Default for Config {
                     name: "File".to_string(),
                     file_path: make_default_image_path(),
                     file_name: "%r12".to_string(),
+                    expire: None,
                 }),
                 Uploader::HTTP(HttpUploader {
                     name: "imgur".to_string(),
@@ -174,14 +349,40 @@ impl Default for Config {
                         "http://imgur.com/delete/$json:data.deletehash$".to_string(),
                     ),
                     error_message: None,
+                    response_script: None,
+                    album: Some(Album {
+                        request_method: "POST".to_string(),
+                        request_url: "https://api.imgur.com/3/album".to_string(),
+                        item: "$json:data.deletehash$".to_string(),
+                        item_form_name: "deletehashes[]".to_string(),
+                        url: "https://imgur.com/a/$json:data.id$".to_string(),
+                        deletion_url: Some(
+                            "http://imgur.com/delete/$json:data.deletehash$".to_string(),
+                        ),
+                    }),
+                    payload: None,
+                    encrypt: false,
+                    expire: None,
+                    deletion_method: None,
+                    clipboard_template: None,
                 }),
             ],
             screenshotter: None,
             last_index: 0,
             copy_to_clipboard: true,
             copy_url_to_clipboard: false,
+            clipboard_template: None,
+            primary_uploader: None,
             show_notification: true,
             freeze_screen: true,
+            selection_monitor: None,
+            display: None,
+            screen: None,
+            exclude_window_frame: false,
+            show_cursor: false,
+            hooks: Hooks::default(),
+            viewer_url: None,
+            cache: CacheConfig::default(),
 
             #[cfg(target_os = "linux")]
             tessdata_path: Some("/usr/share/tessdata/".to_string()),
@@ -192,7 +393,7 @@ impl Default for Config {
     }
 }
 
-mod sharex {
+pub mod sharex {
     use serde_derive::{Deserialize, Serialize};
     use std::collections::HashMap;
 
@@ -0,0 +1,166 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+// Uploads are encrypted with AES-256-GCM under a fresh key, the uploaded payload is the 12 byte nonce followed by
+// the ciphertext. The key never leaves the machine except in the fragment of the returned URL, which browsers
+// don't send to the server, so the host only ever sees the encrypted bytes.
+const NONCE_SIZE: usize = 12;
+
+pub struct Encrypted {
+    pub data: Vec<u8>,
+    pub key: String, // base64url without padding, as it appears in the URL fragment
+}
+
+pub fn encrypt(data: &[u8]) -> Result<Encrypted, Box<dyn std::error::Error>> {
+    let key = Aes256Gcm::generate_key(OsRng);
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+
+    let ciphertext = Aes256Gcm::new(&key)
+        .encrypt(&nonce, data)
+        .map_err(|_| "Failed to encrypt the file")?;
+
+    let mut payload = nonce.to_vec();
+    payload.extend(ciphertext);
+
+    Ok(Encrypted {
+        data: payload,
+        key: URL_SAFE_NO_PAD.encode(key),
+    })
+}
+
+pub fn decrypt(data: &[u8], key: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let key = URL_SAFE_NO_PAD.decode(key)?;
+    if key.len() != 32 {
+        return Err(format!("Expected a 256 bit key, got {} bits", key.len() * 8).into());
+    }
+
+    if data.len() < NONCE_SIZE {
+        return Err("The file is too short to be encrypted by delenix".into());
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+
+    let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt the file, the key is wrong or the file was modified")?;
+
+    Ok(plaintext)
+}
+
+// The link handed out for an encrypted upload. Without a viewer it's the upload URL itself with the key appended,
+// which `delenix fetch` understands. With one, the viewer page is given the upload URL as its src parameter.
+pub fn share_url(
+    url: &str,
+    key: &str,
+    viewer_url: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut url = match viewer_url {
+        Some(viewer_url) => reqwest::Url::parse_with_params(viewer_url, &[("src", url)])?,
+        None => reqwest::Url::parse(url)?,
+    };
+
+    url.set_fragment(Some(key));
+
+    Ok(url.to_string())
+}
+
+// Downloads and decrypts a link returned by share_url, both the plain and viewer forms are accepted
+pub fn fetch(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (src, key) = parse_share_url(url)?;
+
+    let res = reqwest::blocking::get(src)?;
+    if !res.status().is_success() {
+        return Err(format!("Failed to download the file: {}", res.status()).into());
+    }
+
+    decrypt(&res.bytes()?, &key)
+}
+
+// Splits a link returned by share_url into the URL of the encrypted upload and its key
+fn parse_share_url(url: &str) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let mut url = reqwest::Url::parse(url)?;
+
+    let key = match url.fragment() {
+        Some(key) if !key.is_empty() => key.to_string(),
+        _ => return Err("The URL has no key in its fragment".into()),
+    };
+    url.set_fragment(None);
+
+    let src = url
+        .query_pairs()
+        .find(|(k, _)| k == "src")
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_else(|| url.to_string());
+
+    Ok((src, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = b"some image bytes";
+        let encrypted = encrypt(data).unwrap();
+
+        assert_eq!(encrypted.data.len(), NONCE_SIZE + data.len() + 16);
+        assert!(!encrypted.data.windows(data.len()).any(|w| w == data));
+        assert_eq!(decrypt(&encrypted.data, &encrypted.key).unwrap(), data);
+    }
+
+    #[test]
+    fn fresh_key_and_nonce() {
+        let a = encrypt(b"same").unwrap();
+        let b = encrypt(b"same").unwrap();
+
+        assert_ne!(a.key, b.key);
+        assert_ne!(a.data, b.data);
+    }
+
+    #[test]
+    fn rejects_wrong_key_and_tampering() {
+        let encrypted = encrypt(b"secret").unwrap();
+        let other = encrypt(b"secret").unwrap();
+        assert!(decrypt(&encrypted.data, &other.key).is_err());
+
+        let mut tampered = encrypted.data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&tampered, &encrypted.key).is_err());
+
+        assert!(decrypt(&encrypted.data[..NONCE_SIZE - 1], &encrypted.key).is_err());
+        assert!(decrypt(&encrypted.data, "dG9vc2hvcnQ").is_err());
+        assert!(decrypt(&encrypted.data, "not base64!").is_err());
+    }
+
+    #[test]
+    fn share_url_round_trip() {
+        let key = encrypt(b"x").unwrap().key;
+        let upload = "https://files.example.com/a b.bin?x=1&y=2";
+
+        let plain = share_url(upload, &key, None).unwrap();
+        assert!(plain.ends_with(&format!("#{}", key)));
+        assert_eq!(
+            parse_share_url(&plain).unwrap(),
+            (
+                "https://files.example.com/a%20b.bin?x=1&y=2".to_string(),
+                key.clone()
+            )
+        );
+
+        let viewer = share_url(upload, &key, Some("https://viewer.example.com/")).unwrap();
+        assert!(viewer.starts_with("https://viewer.example.com/?src="));
+        assert_eq!(
+            parse_share_url(&viewer).unwrap(),
+            (upload.to_string(), key.clone())
+        );
+    }
+
+    #[test]
+    fn share_url_without_key() {
+        assert!(parse_share_url("https://files.example.com/a.bin").is_err());
+        assert!(parse_share_url("https://files.example.com/a.bin#").is_err());
+        assert!(parse_share_url("not a url").is_err());
+    }
+}
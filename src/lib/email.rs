@@ -0,0 +1,91 @@
+use lettre::message::{header::ContentType, Attachment, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::{
+    config::{Config, EmailUploader, SmtpSecurity},
+    upload::UploadResult,
+    util,
+};
+
+// Sends the file to the uploader's recipients, either attached or embedded in an HTML body.
+// The subject and body support the same placeholders as file names, as well as $filename$ and $window_title$.
+pub fn send(
+    conf: &Config,
+    u: &EmailUploader,
+    data: &[u8],
+    format: &str,
+    filename: &str,
+    window_title: Option<&str>,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let vars = [
+        ("filename", filename.to_string()),
+        ("window_title", window_title.unwrap_or("").to_string()),
+    ];
+
+    let subject = util::fill_template(&conf.make_filename(Some(&u.subject)), &vars);
+    let body = util::fill_template(&conf.make_filename(Some(&u.body)), &vars);
+
+    let mut builder = Message::builder().from(u.from.parse()?).subject(subject);
+    for to in &u.to {
+        builder = builder.to(to.parse()?);
+    }
+
+    let content_type = ContentType::parse(util::mime_type(format))?;
+
+    let message = if u.inline {
+        let html = format!(
+            "<p>{}</p><img src=\"cid:screenshot\" alt=\"{}\">",
+            escape_html(&body).replace('\n', "<br>"),
+            escape_html(filename)
+        );
+
+        builder.multipart(
+            MultiPart::related()
+                .singlepart(SinglePart::html(html))
+                .singlepart(
+                    Attachment::new_inline("screenshot".to_string())
+                        .body(data.to_vec(), content_type),
+                ),
+        )?
+    } else {
+        builder.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(body))
+                .singlepart(
+                    Attachment::new(filename.to_string()).body(data.to_vec(), content_type),
+                ),
+        )?
+    };
+
+    let mut transport = match u.security {
+        SmtpSecurity::Tls => SmtpTransport::relay(&u.host)?,
+        SmtpSecurity::StartTls => SmtpTransport::starttls_relay(&u.host)?,
+        SmtpSecurity::None => SmtpTransport::builder_dangerous(&u.host),
+    };
+
+    if let Some(port) = u.port {
+        transport = transport.port(port);
+    }
+
+    if let (Some(username), Some(password)) = (&u.username, &u.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    let error_message = match transport.build().send(&message) {
+        Ok(_) => None,
+        Err(e) => Some(e.to_string()),
+    };
+
+    Ok(UploadResult {
+        error_message,
+        ..UploadResult::new(&u.name)
+    })
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
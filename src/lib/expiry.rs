@@ -0,0 +1,233 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    cache,
+    config::{Config, Uploader},
+    upload::UploadResult,
+    util::{self, make_default_expiry_path},
+};
+
+// Deletions that failed this many times are given up on, so a host that's gone doesn't get retried forever
+const MAX_ATTEMPTS: u32 = 10;
+
+// An upload to delete once its time is up. The schedule is kept on disk, so uploads made while the daemon isn't
+// running, or before it restarted, are still deleted, late if need be.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExpiryEntry {
+    pub uploader_name: String,
+    pub expires_at: i64, // Unix timestamp
+    pub url: Option<String>,
+    pub deletion_url: Option<String>, // Requested to delete a remote upload
+    pub file_path: Option<String>,    // Removed to delete a local one
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+lazy_static! {
+    static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+// Records the upload for deletion after ttl seconds. Only results with a deletion URL or a local file can expire,
+// failed uploads have nothing to delete and are skipped.
+pub fn schedule(result: &UploadResult, ttl: u64) -> Result<(), Box<dyn std::error::Error>> {
+    if result.error_message.is_some() {
+        return Ok(());
+    }
+
+    if result.deletion_url.is_none() && result.file_path.is_none() {
+        return Err(format!("{} gave no deletion URL", result.uploader_name).into());
+    }
+
+    let expires_at = i64::try_from(ttl)
+        .ok()
+        .and_then(|ttl| chrono::Utc::now().timestamp().checked_add(ttl))
+        .ok_or("The expiry time is too far away")?;
+
+    let entry = ExpiryEntry {
+        uploader_name: result.uploader_name.clone(),
+        expires_at,
+        url: result.url.clone(),
+        deletion_url: result.deletion_url.clone(),
+        file_path: result.file_path.clone(),
+        attempts: 0,
+    };
+
+    locked(|| {
+        let mut entries = load()?;
+        entries.push(entry);
+        save(&entries)
+    })
+    .map_err(|e| format!("Failed to save the expiry schedule: {}", e))?;
+
+    tracing::info!(
+        "{} upload will be deleted in {}s, as long as the daemon is running",
+        result.uploader_name,
+        ttl
+    );
+
+    Ok(())
+}
+
+// Deletes every upload whose time is up, failed deletions are retried the next time round.
+// The schedule isn't held while deleting, since other delenix processes may be adding to it in the meantime.
+pub fn run_due(conf: &Config) {
+    let now = chrono::Utc::now().timestamp();
+
+    let due: Vec<ExpiryEntry> = match locked(load) {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(|e| e.expires_at <= now)
+            .collect(),
+        Err(e) => {
+            tracing::error!("Failed to read the expiry schedule: {}", e);
+            return;
+        }
+    };
+
+    if due.is_empty() {
+        return;
+    }
+
+    let mut done = Vec::new(); // deleted or given up on
+    let mut failed = Vec::new();
+
+    for entry in due {
+        match delete(conf, &entry) {
+            Ok(()) => {
+                tracing::info!(
+                    "Deleted expired upload {}",
+                    entry
+                        .url
+                        .as_ref()
+                        .or(entry.file_path.as_ref())
+                        .map(String::as_str)
+                        .unwrap_or(&entry.uploader_name)
+                );
+
+                if let Some(ref url) = entry.url {
+                    cache::remove(conf, &entry.uploader_name, url);
+                }
+
+                done.push(entry);
+            }
+            Err(e) if entry.attempts + 1 >= MAX_ATTEMPTS => {
+                tracing::error!(
+                    "Giving up on deleting an upload to {}: {}",
+                    entry.uploader_name,
+                    e
+                );
+                done.push(entry);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to delete an upload to {}, will retry: {}",
+                    entry.uploader_name,
+                    e
+                );
+                failed.push(entry);
+            }
+        }
+    }
+
+    let saved = locked(|| {
+        let mut entries = load()?;
+
+        entries.retain(|e| !done.iter().any(|d| d.is(e)));
+        for entry in entries.iter_mut() {
+            if failed.iter().any(|f| f.is(entry)) {
+                entry.attempts += 1;
+            }
+        }
+
+        save(&entries)
+    });
+
+    if let Err(e) = saved {
+        tracing::error!("Failed to save the expiry schedule: {}", e);
+    }
+}
+
+impl ExpiryEntry {
+    // Whether both entries are for the same upload, regardless of how often deleting it was attempted
+    fn is(&self, other: &Self) -> bool {
+        self.uploader_name == other.uploader_name
+            && self.expires_at == other.expires_at
+            && self.url == other.url
+            && self.deletion_url == other.deletion_url
+            && self.file_path == other.file_path
+    }
+}
+
+fn locked<T>(f: impl FnOnce() -> T) -> T {
+    let _lock = LOCK.lock().unwrap();
+    f()
+}
+
+fn delete(conf: &Config, entry: &ExpiryEntry) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(ref path) = entry.file_path {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if let Some(ref deletion_url) = entry.deletion_url {
+        // the uploader's headers usually carry the auth the host wants for deleting too
+        let uploader = match conf.uploader(&entry.uploader_name) {
+            Some(Uploader::HTTP(u)) => Some(u),
+            _ => None,
+        };
+
+        let method = uploader
+            .and_then(|u| u.deletion_method.as_deref())
+            .unwrap_or("GET");
+
+        let client = reqwest::blocking::Client::new();
+        let mut req = client.request(
+            reqwest::Method::from_bytes(method.to_uppercase().as_bytes())?,
+            deletion_url,
+        );
+
+        if let Some(headers) = uploader.and_then(|u| u.headers.as_ref()) {
+            for (k, v) in headers {
+                req = req.header(k, v);
+            }
+        }
+
+        let res = req.send()?;
+
+        // already gone is as good as deleted
+        if !res.status().is_success() && res.status() != reqwest::StatusCode::NOT_FOUND {
+            return Err(format!("{}: {}", res.status(), res.text()?).into());
+        }
+    }
+
+    Ok(())
+}
+
+// Unlike the upload cache, a schedule that can't be read is an error, overwriting it would lose deletions
+fn load() -> Result<Vec<ExpiryEntry>, Box<dyn std::error::Error>> {
+    let s = match std::fs::read_to_string(make_default_expiry_path()) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(serde_json::from_str(&s)?)
+}
+
+fn save(entries: &[ExpiryEntry]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = make_default_expiry_path();
+
+    if let Some(parent) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    util::write_atomic(&path, serde_json::to_string_pretty(entries)?.as_bytes())?;
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{upload::UploadResult, util};
+
+// Commands run at different points of a capture and upload, they receive the results as DELENIX_* environment
+// variables and as JSON on stdin. A hook failing is logged but never stops the capture or upload.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    #[serde(default)]
+    pub after_capture: Vec<Hook>, // Run once the screenshot is taken, before it is uploaded
+    #[serde(default)]
+    pub after_upload: Vec<Hook>, // Run once for every uploader, including failed ones
+    #[serde(default)]
+    pub after_all_uploads: Vec<Hook>, // Run once every uploader is done
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hook {
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub background: bool, // Don't wait for the command to exit, e.g. when opening an editor
+}
+
+// The JSON written to the stdin of after_capture hooks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureInfo {
+    pub file: String,
+    pub format: String,
+    pub size: usize,
+}
+
+impl Hooks {
+    // The capture is written to a temporary file for the hooks, which is removed once they're done
+    pub fn after_capture(&self, data: &[u8], format: &str) {
+        if self.after_capture.is_empty() {
+            return;
+        }
+
+        let file = match util::write_temp_file(data, format) {
+            Ok(path) => Arc::new(TempFile(path)),
+            Err(e) => {
+                tracing::error!("Failed to write capture for hooks: {}", e);
+                return;
+            }
+        };
+
+        let info = CaptureInfo {
+            file: file.0.to_string_lossy().into_owned(),
+            format: format.to_string(),
+            size: data.len(),
+        };
+
+        let env = vec![
+            ("DELENIX_FILE", info.file.clone()),
+            ("DELENIX_FORMAT", info.format.clone()),
+            ("DELENIX_SIZE", info.size.to_string()),
+        ];
+
+        // background hooks hold on to the file too, so it's removed once the last of them exits
+        run_all(&self.after_capture, &env, &info, Some(&file));
+    }
+
+    pub fn after_upload(&self, result: &UploadResult) {
+        if self.after_upload.is_empty() {
+            return;
+        }
+
+        let fields = [
+            ("DELENIX_URL", &result.url),
+            ("DELENIX_THUMBNAIL_URL", &result.thumbnail_url),
+            ("DELENIX_DELETION_URL", &result.deletion_url),
+            ("DELENIX_FILE", &result.file_path),
+            ("DELENIX_ERROR", &result.error_message),
+        ];
+
+        let mut env = vec![("DELENIX_UPLOADER", result.uploader_name.clone())];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                env.push((name, value.clone()));
+            }
+        }
+
+        run_all(&self.after_upload, &env, result, None);
+    }
+
+    // DELENIX_URL and DELENIX_FILE are the first successful ones, DELENIX_URLS and DELENIX_FILES hold all of them
+    pub fn after_all_uploads(&self, results: &[UploadResult]) {
+        if self.after_all_uploads.is_empty() {
+            return;
+        }
+
+        let urls: Vec<&str> = results.iter().filter_map(|r| r.url.as_deref()).collect();
+        let files: Vec<&str> = results
+            .iter()
+            .filter_map(|r| r.file_path.as_deref())
+            .collect();
+        let failed = results.iter().filter(|r| r.error_message.is_some()).count();
+
+        let mut env = vec![
+            ("DELENIX_URLS", urls.join("\n")),
+            ("DELENIX_FILES", files.join("\n")),
+            ("DELENIX_FAILED", failed.to_string()),
+        ];
+        if let Some(url) = urls.first() {
+            env.push(("DELENIX_URL", url.to_string()));
+        }
+        if let Some(file) = files.first() {
+            env.push(("DELENIX_FILE", file.to_string()));
+        }
+
+        run_all(&self.after_all_uploads, &env, &results, None);
+    }
+}
+
+// A temporary file the hooks are given, removed once every hook is done with it
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            tracing::warn!(
+                "Failed to remove temporary file {}: {}",
+                self.0.display(),
+                e
+            );
+        }
+    }
+}
+
+fn run_all<T: serde::Serialize>(
+    hooks: &[Hook],
+    env: &[(&str, String)],
+    input: &T,
+    file: Option<&Arc<TempFile>>,
+) {
+    let stdin = match serde_json::to_vec(input) {
+        Ok(stdin) => stdin,
+        Err(e) => {
+            tracing::error!("Failed to serialize hook input: {}", e);
+            return;
+        }
+    };
+
+    for hook in hooks {
+        if let Err(e) = hook.run(env, &stdin, file.cloned()) {
+            tracing::error!("Hook {} failed: {}", hook.path, e);
+        }
+    }
+}
+
+impl Hook {
+    // file is kept until the hook exits, background hooks included
+    fn run(
+        &self,
+        env: &[(&str, String)],
+        stdin: &[u8],
+        file: Option<Arc<TempFile>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // nobody reads the output of a background hook, so don't let it fill up a pipe
+        let output = || {
+            if self.background {
+                Stdio::null()
+            } else {
+                Stdio::piped()
+            }
+        };
+
+        let mut child = Command::new(&self.path)
+            .args(&self.args)
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(output())
+            .stderr(output())
+            .spawn()?;
+
+        if let Some(mut pipe) = child.stdin.take() {
+            // the hook may exit without reading stdin, which is fine
+            if let Err(e) = pipe.write_all(stdin) {
+                tracing::debug!("Failed to write to {} stdin: {}", self.path, e);
+            }
+        }
+
+        if self.background {
+            // wait on it elsewhere, so it doesn't linger as a zombie in the daemon once it exits
+            let path = self.path.clone();
+            std::thread::spawn(move || {
+                match child.wait() {
+                    Ok(status) if !status.success() => {
+                        tracing::warn!("Background hook {} exited with {}", path, status)
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Failed to wait for background hook {}: {}", path, e),
+                }
+
+                drop(file);
+            });
+
+            return Ok(());
+        }
+
+        let output = child.wait_with_output()?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.trim().is_empty() {
+            tracing::info!("{}: {}", self.path, stdout.trim());
+        }
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("exited with {}: {}", output.status, stderr.trim()).into());
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,14 @@
+pub mod batch;
+pub mod cache;
 pub mod clipboard;
 pub mod config;
+pub mod crypto;
+pub mod email;
+pub mod expiry;
+pub mod hooks;
 pub mod notification;
 pub mod ocr;
 pub mod screenshot;
+pub mod script;
 pub mod upload;
 pub mod util;
@@ -1,6 +1,7 @@
 use image::RgbaImage;
 use serde_derive::{Deserialize, Serialize};
 use std::io::Cursor;
+use std::str::FromStr;
 
 use crate::config::Config;
 
@@ -35,9 +36,155 @@ pub fn as_jpeg(
     Ok(data.into_inner())
 }
 
+// Returned when the user cancels an interactive selection, so callers can tell it apart from a failed capture
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "User cancelled region selection")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+// Picks one monitor out of the ones RandR reports, in the order it reports them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MonitorSelector {
+    Index(usize),
+    Name(String), // Output name, e.g. DP-1 or HDMI-0
+    Current,      // The monitor under the pointer
+}
+
+impl FromStr for MonitorSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("current") {
+            return Ok(Self::Current);
+        }
+
+        match s.parse() {
+            Ok(index) => Ok(Self::Index(index)),
+            Err(_) if !s.is_empty() => Ok(Self::Name(s.to_string())),
+            Err(_) => Err("Expected a monitor index, name or \"current\"".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Monitor {
+    pub name: String,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub primary: bool,
+}
+
+impl Monitor {
+    fn contains(&self, x: i16, y: i16) -> bool {
+        x >= self.x
+            && y >= self.y
+            && (x as i32) < self.x as i32 + self.width as i32
+            && (y as i32) < self.y as i32 + self.height as i32
+    }
+}
+
+// A window as the window manager sees it, see Session::list_windows
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub title: Option<String>,
+    pub class: Option<String>, // The second part of WM_CLASS, e.g. firefox
+    pub instance: Option<String>, // The first part, usually the name the program was started as
+    pub pid: Option<u32>,
+    pub desktop: Option<u32>, // 0xFFFFFFFF when shown on all desktops
+    pub x: i16,               // Relative to the root window, unlike what GetGeometry gives
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub mapped: bool,
+    pub visible: bool, // Viewable and not minimised
+}
+
+// Narrows the window list down to one window, for capturing without any interaction
+#[derive(Clone, Debug, Default)]
+pub struct WindowMatch {
+    pub class: Option<String>, // The class or instance name, ignoring case
+    pub title: Option<regex::Regex>,
+    pub topmost: bool, // Take the topmost of several matches instead of failing
+}
+
+impl WindowMatch {
+    fn matches(&self, window: &WindowInfo) -> bool {
+        let class_matches = self.class.as_ref().is_none_or(|class| {
+            [&window.class, &window.instance].iter().any(|name| {
+                name.as_ref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(class))
+            })
+        });
+
+        let title_matches = self.title.as_ref().is_none_or(|title| {
+            window
+                .title
+                .as_ref()
+                .is_some_and(|name| title.is_match(name))
+        });
+
+        class_matches && title_matches
+    }
+
+    // The one visible window that matches, out of windows listed bottom to top like Session::list_windows does
+    pub fn find<'a>(&self, windows: &'a [WindowInfo]) -> Result<&'a WindowInfo, String> {
+        let matches: Vec<&WindowInfo> = windows
+            .iter()
+            .filter(|window| window.visible && self.matches(window))
+            .collect();
+
+        match matches[..] {
+            [] => Err(format!("No visible window matches {}", self)),
+            [window] => Ok(window),
+            [.., topmost] if self.topmost => Ok(topmost),
+            _ => {
+                let list: String = matches
+                    .iter()
+                    .map(|window| {
+                        format!(
+                            "\n  {:#010x}  {}  {}",
+                            window.id,
+                            window.class.as_deref().unwrap_or("-"),
+                            window.title.as_deref().unwrap_or("")
+                        )
+                    })
+                    .collect();
+
+                Err(format!(
+                    "{} windows match {}, narrow it down or take the topmost one:{}",
+                    matches.len(),
+                    self,
+                    list
+                ))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for WindowMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.class, &self.title) {
+            (Some(class), Some(title)) => write!(f, "class {} and title /{}/", class, title),
+            (Some(class), None) => write!(f, "class {}", class),
+            (None, Some(title)) => write!(f, "title /{}/", title),
+            (None, None) => write!(f, "any window"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ScreenshotType {
     Region(RegionSelection), // x, y, width, height
+    Monitor(MonitorSelector),
 
     #[cfg(target_os = "linux")]
     Window(x11rb::protocol::xproto::Window),
@@ -50,26 +197,71 @@ pub enum ScreenshotType {
 impl Config {
     // returns a Vec<u8> of the image data in PNG format
     pub fn screenshot(&self, typ: ScreenshotType) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        match self.screenshotter {
-            None => match typ {
-                ScreenshotType::Region(selection) => {
-                    let data = capture_region(&selection)?;
+        // a custom screenshotter doesn't need the X server, so only connect for the built-in one
+        let png = match self.screenshotter {
+            Some(ref s) => s.capture()?,
+            None => self.capture(&self.session()?, typ)?,
+        };
 
-                    Ok(as_png(data, selection.w, selection.h)?)
-                }
-                ScreenshotType::Window(window) => {
-                    let (data, (w, h)) = capture_window(window)?;
+        self.hooks.after_capture(&png, "png");
 
-                    Ok(as_png(data, w, h)?)
-                }
-                ScreenshotType::Screen => {
-                    let (data, (w, h)) = capture_screen()?;
+        Ok(png)
+    }
+
+    // Opens a session on the configured display and screen
+    pub fn session(&self) -> Result<Session, Box<dyn std::error::Error>> {
+        Session::connect(self.display.as_deref(), self.screen)
+    }
+
+    // Like screenshot, but through an already open session, which saves connecting for every capture
+    pub fn screenshot_with(
+        &self,
+        session: &Session,
+        typ: ScreenshotType,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let png = match self.screenshotter {
+            Some(ref s) => s.capture()?,
+            None => self.capture(session, typ)?,
+        };
+
+        self.hooks.after_capture(&png, "png");
+
+        Ok(png)
+    }
 
-                    Ok(as_png(data, w, h)?)
+    fn capture(
+        &self,
+        session: &Session,
+        typ: ScreenshotType,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let selection = match typ {
+            ScreenshotType::Region(selection) => selection,
+            ScreenshotType::Window(window) => {
+                let frame = !self.exclude_window_frame;
+
+                // through the compositor when there is one, so windows covered by others come out right
+                match session.capture_composited(window, frame, self.show_cursor) {
+                    Ok(Some(image)) => {
+                        let (w, h) = image.dimensions();
+                        return as_png(image.into_raw(), w as u16, h as u16);
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::debug!("Composite capture failed, using the screen: {}", e),
                 }
-            },
-            Some(ref s) => s.capture(),
+
+                session.window_region(window, frame)?
+            }
+            ScreenshotType::Monitor(selector) => (&session.find_monitor(&selector)?).into(),
+            ScreenshotType::Screen => session.screen_region()?,
+        };
+
+        let mut data = session.capture_region(&selection)?;
+
+        if self.show_cursor {
+            session.draw_cursor(&mut data, &selection);
         }
+
+        as_png(data, selection.w, selection.h)
     }
 }
 
@@ -95,33 +287,286 @@ pub mod custom {
 
 #[cfg(target_os = "linux")]
 mod linux {
-    use std::ffi::CString;
-    use std::sync::{Arc, RwLock};
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex, RwLock};
 
     use gdk::CursorType;
     use gdk_pixbuf::PixbufLoader;
     use serde_derive::{Deserialize, Serialize};
-    use x11rb::connection::Connection;
+    use x11rb::connection::{Connection, RequestConnection};
     use x11rb::errors::ReplyOrIdError;
-    use x11rb::protocol::xproto::{self, ConnectionExt, GetGeometryReply, InternAtomReply, Window};
+    use x11rb::protocol::composite::{self, ConnectionExt as _};
+    use x11rb::protocol::randr::ConnectionExt as _;
+    use x11rb::protocol::shm::{self, ConnectionExt as _};
+    use x11rb::protocol::xfixes::{self, ConnectionExt as _};
+    use x11rb::protocol::xproto::{self, ConnectionExt, GetGeometryReply, Window};
     use x11rb::rust_connection::RustConnection;
 
-    // Function to get the root window of the X11 display
-    fn get_root_window(connection: &RustConnection) -> Result<Window, ReplyOrIdError> {
-        let setup = connection.setup();
-        Ok(setup.roots[0].root)
+    use super::{Monitor, MonitorSelector, WindowInfo};
+
+    x11rb::atom_manager! {
+        pub Atoms: AtomsCookie {
+            UTF8_STRING,
+            _NET_WM_NAME,
+            _NET_CLIENT_LIST_STACKING,
+            _NET_WM_PID,
+            _NET_WM_DESKTOP,
+            _NET_WM_STATE,
+            _NET_WM_STATE_HIDDEN,
+            _NET_FRAME_EXTENTS,
+        }
+    }
+
+    // A connection to the X server along with what every request needs from it. Connecting and interning atoms
+    // is done once, the daemon keeps its session for as long as it runs so the shared memory segment is reused too.
+    pub struct Session {
+        connection: RustConnection,
+        display: Option<String>, // None for $DISPLAY
+        screen_num: usize,
+        root: Window,
+        atoms: Atoms,
+        shm: Mutex<Shm>,
     }
 
-    // Function to get the windows present on the screen
-    pub fn get_windows() -> Result<Vec<Window>, ReplyOrIdError> {
-        let (connection, _screen_num) =
-            RustConnection::connect(None).expect("Failed to connect to X server");
-        let root_window = get_root_window(&connection)?;
+    impl Session {
+        // Connects to the display, e.g. ":99", or $DISPLAY if None. The screen defaults to the one the display
+        // name gives, which is screen 0 unless it ends in one, like ":99.1".
+        pub fn connect(
+            display: Option<&str>,
+            screen: Option<usize>,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let (connection, default_screen) =
+                RustConnection::connect(display).map_err(|e| match display {
+                    Some(display) => {
+                        format!("Failed to connect to the X server at {}: {}", display, e)
+                    }
+                    None => format!("Failed to connect to the X server, is DISPLAY set? {}", e),
+                })?;
+
+            let screen_num = screen.unwrap_or(default_screen);
+            let screens = connection.setup().roots.len();
+            if screen_num >= screens {
+                return Err(format!(
+                    "Screen {} doesn't exist, the display has {} screen(s)",
+                    screen_num, screens
+                )
+                .into());
+            }
+
+            let root = connection.setup().roots[screen_num].root;
+            let atoms = Atoms::new(&connection)?.reply()?;
+
+            Ok(Self {
+                connection,
+                display: display.map(str::to_string),
+                screen_num,
+                root,
+                atoms,
+                shm: Mutex::new(Shm::Untried),
+            })
+        }
+
+        pub fn connection(&self) -> &RustConnection {
+            &self.connection
+        }
+
+        pub fn screen_num(&self) -> usize {
+            self.screen_num
+        }
+
+        pub fn root(&self) -> Window {
+            self.root
+        }
+
+        // The whole screen, as a region to capture
+        pub fn screen_region(&self) -> Result<RegionSelection, ReplyOrIdError> {
+            Ok(self.connection.get_geometry(self.root)?.reply()?.into())
+        }
+
+        // The windows the window manager manages, bottom to top
+        pub fn list_windows(&self) -> Result<Vec<WindowInfo>, Box<dyn std::error::Error>> {
+            let clients: Vec<Window> = self
+                .get_property32(
+                    self.root,
+                    self.atoms._NET_CLIENT_LIST_STACKING,
+                    xproto::AtomEnum::WINDOW,
+                )?
+                .ok_or(
+                    "The window manager doesn't set _NET_CLIENT_LIST_STACKING, is one running?",
+                )?;
+
+            let mut windows = Vec::with_capacity(clients.len());
+            for window in clients {
+                // windows may be gone by the time they're asked about
+                match self.window_info(window) {
+                    Ok(info) => windows.push(info),
+                    Err(e) => tracing::debug!("Skipping window {:#x}: {}", window, e),
+                }
+            }
+
+            Ok(windows)
+        }
+
+        pub fn window_info(
+            &self,
+            window: Window,
+        ) -> Result<WindowInfo, Box<dyn std::error::Error>> {
+            let attributes = self.connection.get_window_attributes(window)?.reply()?;
+            let region = self.root_geometry(window)?;
+            let (instance, class) = self.get_window_class(window)?;
+
+            let pid = self
+                .get_property32(window, self.atoms._NET_WM_PID, xproto::AtomEnum::CARDINAL)?
+                .and_then(|values| values.first().copied());
+            let desktop = self
+                .get_property32(
+                    window,
+                    self.atoms._NET_WM_DESKTOP,
+                    xproto::AtomEnum::CARDINAL,
+                )?
+                .and_then(|values| values.first().copied());
+            let hidden = self
+                .get_property32(window, self.atoms._NET_WM_STATE, xproto::AtomEnum::ATOM)?
+                .is_some_and(|state| state.contains(&self.atoms._NET_WM_STATE_HIDDEN));
+
+            Ok(WindowInfo {
+                id: window,
+                title: self.get_window_name(window)?,
+                class,
+                instance,
+                pid,
+                desktop,
+                x: region.x,
+                y: region.y,
+                width: region.w,
+                height: region.h,
+                mapped: attributes.map_state != xproto::MapState::UNMAPPED,
+                visible: attributes.map_state == xproto::MapState::VIEWABLE && !hidden,
+            })
+        }
 
-        let cookie = connection.query_tree(root_window)?;
-        let reply = cookie.reply()?;
+        // A property made of 32 bit values, None if the window doesn't have it
+        fn get_property32(
+            &self,
+            window: Window,
+            property: xproto::Atom,
+            type_: xproto::AtomEnum,
+        ) -> Result<Option<Vec<u32>>, ReplyOrIdError> {
+            let reply = self
+                .connection
+                .get_property(false, window, property, type_, 0, u32::MAX)?
+                .reply()?;
+
+            Ok(reply.value32().map(|values| values.collect()))
+        }
+
+        // WM_CLASS holds the instance and class names, one after the other
+        fn get_window_class(
+            &self,
+            window: Window,
+        ) -> Result<(Option<String>, Option<String>), ReplyOrIdError> {
+            let reply = self
+                .connection
+                .get_property(
+                    false,
+                    window,
+                    xproto::AtomEnum::WM_CLASS,
+                    xproto::AtomEnum::STRING,
+                    0,
+                    u32::MAX,
+                )?
+                .reply()?;
+
+            let mut names = reply
+                .value
+                .split(|b| *b == 0)
+                .filter(|name| !name.is_empty())
+                .map(|name| String::from_utf8_lossy(name).into_owned());
+
+            Ok((names.next(), names.next()))
+        }
+
+        // Where the window is, relative to the root rather than its parent, which is usually the frame
+        pub fn root_geometry(&self, window: Window) -> Result<RegionSelection, ReplyOrIdError> {
+            let geometry = self.connection.get_geometry(window)?.reply()?;
+            let position = self
+                .connection
+                .translate_coordinates(window, self.root, 0, 0)?
+                .reply()?;
+
+            Ok(RegionSelection {
+                x: position.dst_x,
+                y: position.dst_y,
+                w: geometry.width,
+                h: geometry.height,
+                pixbuf: None,
+                pixbuf_origin: (0, 0),
+            })
+        }
+
+        // The windows present on the screen
+        pub fn get_windows(&self) -> Result<Vec<Window>, ReplyOrIdError> {
+            let reply = self.connection.query_tree(self.root)?.reply()?;
+
+            Ok(reply.children)
+        }
+
+        // The active monitors, as RandR reports them
+        pub fn get_monitors(&self) -> Result<Vec<Monitor>, Box<dyn std::error::Error>> {
+            let reply = self
+                .connection
+                .randr_get_monitors(self.root, true)?
+                .reply()?;
+
+            let mut monitors = Vec::with_capacity(reply.monitors.len());
+            for info in reply.monitors {
+                let name = self.connection.get_atom_name(info.name)?.reply()?.name;
+
+                monitors.push(Monitor {
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                    x: info.x,
+                    y: info.y,
+                    width: info.width,
+                    height: info.height,
+                    primary: info.primary,
+                });
+            }
+
+            Ok(monitors)
+        }
 
-        Ok(reply.children)
+        pub fn find_monitor(
+            &self,
+            selector: &MonitorSelector,
+        ) -> Result<Monitor, Box<dyn std::error::Error>> {
+            let monitors = self.get_monitors()?;
+
+            let monitor = match selector {
+                MonitorSelector::Index(index) => monitors.get(*index).cloned(),
+                MonitorSelector::Name(name) => monitors
+                    .iter()
+                    .find(|m| m.name.eq_ignore_ascii_case(name))
+                    .cloned(),
+                MonitorSelector::Current => {
+                    let pointer = self.connection.query_pointer(self.root)?.reply()?;
+
+                    monitors
+                        .iter()
+                        .find(|m| m.contains(pointer.root_x, pointer.root_y))
+                        .cloned()
+                }
+            };
+
+            monitor.ok_or_else(|| {
+                let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+                format!(
+                    "No monitor matches {:?}, the monitors are: {}",
+                    selector,
+                    names.join(", ")
+                )
+                .into()
+            })
+        }
     }
 
     struct DragData {
@@ -140,6 +585,9 @@ mod linux {
 
         #[serde(skip_serializing, skip_deserializing)]
         pub pixbuf: Option<gdk_pixbuf::Pixbuf>,
+        // Where the pixbuf sits on the screen, it only covers one monitor when the selection is constrained to it
+        #[serde(skip_serializing, skip_deserializing)]
+        pub pixbuf_origin: (i16, i16),
     }
 
     // we never access pixbuf from another thread, so it's safe.
@@ -153,332 +601,926 @@ mod linux {
                 w: reply.width,
                 h: reply.height,
                 pixbuf: None,
+                pixbuf_origin: (0, 0),
             }
         }
     }
 
-    // Function to capture a region of the screen specified by coordinates (x, y, width, height)
-    pub fn capture_region(selection: &RegionSelection) -> Result<Vec<u8>, ReplyOrIdError> {
-        // if we already have a pixbuf, likely from freeze, we should use that
-        if let Some(pixbuf) = &selection.pixbuf {
-            let region_pixbuf = pixbuf.new_subpixbuf(
-                selection.x as i32,
-                selection.y as i32,
-                selection.w as i32,
-                selection.h as i32,
-            );
+    impl From<&Monitor> for RegionSelection {
+        fn from(monitor: &Monitor) -> Self {
+            Self {
+                x: monitor.x,
+                y: monitor.y,
+                w: monitor.width,
+                h: monitor.height,
+                pixbuf: None,
+                pixbuf_origin: (0, 0),
+            }
+        }
+    }
+
+    // Captures through a shared memory segment instead of having the pixels sent over the socket, which is a lot
+    // faster for big or repeated captures. Only works when the X server runs on the same machine.
+    struct ShmCapturer {
+        seg: shm::Seg, // 0 until the server has attached the segment
+        addr: *mut u8,
+        size: usize,
+    }
+
+    // the segment is only ever accessed through &mut self
+    unsafe impl Send for ShmCapturer {}
 
-            // Get the image dimensions
-            let width = region_pixbuf.width();
-            let height = region_pixbuf.height();
+    impl ShmCapturer {
+        fn new(connection: &RustConnection) -> Result<Self, Box<dyn std::error::Error>> {
+            if connection
+                .extension_information(shm::X11_EXTENSION_NAME)?
+                .is_none()
+            {
+                return Err("The X server doesn't support MIT-SHM".into());
+            }
+            connection.shm_query_version()?.reply()?;
 
-            // Determine the color components and depth
-            let n_channels = region_pixbuf.n_channels() as usize;
-            let row_stride = region_pixbuf.rowstride() as usize;
+            Ok(Self {
+                seg: 0,
+                addr: std::ptr::null_mut(),
+                size: 0,
+            })
+        }
 
-            // Get the pixel data
-            let pixels = unsafe { region_pixbuf.pixels() };
+        // The region as RGBA, the segment is grown as needed and kept for the next capture
+        fn capture(
+            &mut self,
+            connection: &RustConnection,
+            root: Window,
+            selection: &RegionSelection,
+        ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let size = selection.w as usize * selection.h as usize * 4;
+            self.reserve(connection, size)?;
+
+            let reply = connection
+                .shm_get_image(
+                    root,
+                    selection.x,
+                    selection.y,
+                    selection.w,
+                    selection.h,
+                    u32::MAX,
+                    xproto::ImageFormat::Z_PIXMAP.into(),
+                    self.seg,
+                    0,
+                )?
+                .reply()?;
 
-            // Convert the pixel data to a raw image buffer
-            let mut raw_image = vec![0u8; width as usize * height as usize * n_channels];
-            for y in 0..height as usize {
-                let src_offset = y * row_stride;
-                let dst_offset = y * width as usize * n_channels;
-                let row_data = &pixels[src_offset..src_offset + width as usize * n_channels];
-                raw_image[dst_offset..dst_offset + width as usize * n_channels]
-                    .copy_from_slice(row_data);
+            // anything but 32 bits per pixel would need converting, leave that to the GetImage path
+            if reply.size as usize != size {
+                return Err(format!("Unsupported pixel format, depth {}", reply.depth).into());
             }
 
-            return Ok(raw_image);
+            let mut data = unsafe { std::slice::from_raw_parts(self.addr, size) }.to_vec();
+            bgr_to_rgb(&mut data);
+
+            Ok(data)
         }
 
-        let (connection, _screen_num) =
-            RustConnection::connect(None).expect("Failed to connect to X server");
-        let root_window = get_root_window(&connection)?;
+        // Makes sure the segment holds at least size bytes, replacing it with a bigger one if it doesn't
+        fn reserve(
+            &mut self,
+            connection: &RustConnection,
+            size: usize,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            if size <= self.size {
+                return Ok(());
+            }
+
+            if self.seg != 0 {
+                if let Err(e) = connection.shm_detach(self.seg) {
+                    tracing::debug!("Failed to detach shared memory segment: {}", e);
+                }
+                self.seg = 0;
+            }
+            self.unmap();
+
+            let shmid = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+            if shmid < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+
+            let addr = unsafe { libc::shmat(shmid, std::ptr::null(), 0) };
+            if addr as isize == -1 {
+                let e = std::io::Error::last_os_error();
+                unsafe { libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut()) };
+                return Err(e.into());
+            }
 
-        let get_image_cookie = connection.get_image(
-            xproto::ImageFormat::Z_PIXMAP,
-            root_window,
-            selection.x,
-            selection.y,
-            selection.w,
-            selection.h,
-            std::u32::MAX,
-        )?;
+            self.addr = addr as *mut u8;
+            self.size = size;
 
-        let reply = get_image_cookie.reply()?;
+            let seg = connection.generate_id()?;
+            let attached = connection.shm_attach(seg, shmid as u32, false)?.check();
 
-        let image_data = reply.data.to_vec();
+            // marked for removal straight away, so the segment is freed once both sides detach, even if we crash
+            unsafe { libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut()) };
 
-        // Convert byte order from BGR to RGB
-        let mut rgb_data = Vec::with_capacity(image_data.len());
+            attached?;
+            self.seg = seg;
 
-        for pixel in image_data.chunks_exact(4) {
-            let b = pixel[0];
-            let g = pixel[1];
-            let r = pixel[2];
-            let a = pixel[3];
-            rgb_data.extend_from_slice(&[r, g, b, a]);
+            Ok(())
         }
 
-        Ok(rgb_data)
+        fn unmap(&mut self) {
+            if !self.addr.is_null() {
+                unsafe { libc::shmdt(self.addr as *const libc::c_void) };
+                self.addr = std::ptr::null_mut();
+                self.size = 0;
+            }
+        }
     }
 
-    // this function should dim the screen, then give the user a drag cursor to select a region
-    pub fn select_region(freeze: bool) -> Result<RegionSelection, Box<dyn std::error::Error>> {
-        use gdk::Cursor;
-        use gtk::prelude::*;
-        use gtk::{Window, WindowPosition, WindowType};
+    // the server detaches its side when the session's connection closes
+    impl Drop for ShmCapturer {
+        fn drop(&mut self) {
+            self.unmap();
+        }
+    }
 
-        let (connection, _screen_num) =
-            RustConnection::connect(None).expect("Failed to connect to X server");
-        let root_window = get_root_window(&connection)?;
+    enum Shm {
+        Untried,
+        Available(Box<ShmCapturer>),
+        Unavailable, // e.g. a remote display, don't bother trying again
+    }
 
-        let get_geometry_cookie = connection.get_geometry(root_window)?;
-        let get_geometry_reply = get_geometry_cookie.reply()?;
+    // X hands out pixels as BGRA (or BGRX), swap them to RGBA in place
+    fn bgr_to_rgb(data: &mut [u8]) {
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
 
-        gtk::init()?;
+    impl Session {
+        // Captures through the shared segment, None if MIT-SHM can't be used and GetImage should be used instead
+        fn capture_shm(&self, selection: &RegionSelection) -> Option<Vec<u8>> {
+            let mut shm = self.shm.lock().unwrap();
+
+            if let Shm::Untried = *shm {
+                *shm = match ShmCapturer::new(&self.connection) {
+                    Ok(capturer) => Shm::Available(Box::new(capturer)),
+                    Err(e) => {
+                        tracing::debug!("Not using MIT-SHM: {}", e);
+                        Shm::Unavailable
+                    }
+                };
+            }
+
+            let capturer = match *shm {
+                Shm::Available(ref mut capturer) => capturer,
+                _ => return None,
+            };
+
+            match capturer.capture(&self.connection, self.root, selection) {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    tracing::debug!("MIT-SHM capture failed, falling back to GetImage: {}", e);
+                    *shm = Shm::Unavailable;
+                    None
+                }
+            }
+        }
+
+        // Function to capture a region of the screen specified by coordinates (x, y, width, height)
+        pub fn capture_region(
+            &self,
+            selection: &RegionSelection,
+        ) -> Result<Vec<u8>, ReplyOrIdError> {
+            // if we already have a pixbuf, likely from freeze, we should use that
+            if let Some(pixbuf) = &selection.pixbuf {
+                let region_pixbuf = pixbuf.new_subpixbuf(
+                    (selection.x - selection.pixbuf_origin.0) as i32,
+                    (selection.y - selection.pixbuf_origin.1) as i32,
+                    selection.w as i32,
+                    selection.h as i32,
+                );
+
+                // Get the image dimensions
+                let width = region_pixbuf.width();
+                let height = region_pixbuf.height();
+
+                // Determine the color components and depth
+                let n_channels = region_pixbuf.n_channels() as usize;
+                let row_stride = region_pixbuf.rowstride() as usize;
+
+                // Get the pixel data
+                let pixels = unsafe { region_pixbuf.pixels() };
+
+                // Convert the pixel data to a raw image buffer
+                let mut raw_image = vec![0u8; width as usize * height as usize * n_channels];
+                for y in 0..height as usize {
+                    let src_offset = y * row_stride;
+                    let dst_offset = y * width as usize * n_channels;
+                    let row_data = &pixels[src_offset..src_offset + width as usize * n_channels];
+                    raw_image[dst_offset..dst_offset + width as usize * n_channels]
+                        .copy_from_slice(row_data);
+                }
+
+                return Ok(raw_image);
+            }
 
-        // Create a GTK window with transparent background
-        let window = Arc::new(Window::new(WindowType::Toplevel));
-        window.set_type_hint(gdk::WindowTypeHint::Dock);
-        window.set_decorated(false);
-        window.set_skip_taskbar_hint(true);
-        window.set_skip_pager_hint(true);
-        window.set_position(WindowPosition::CenterAlways);
-        window.set_app_paintable(true);
+            if let Some(data) = self.capture_shm(selection) {
+                return Ok(data);
+            }
 
-        let screen =
-            gtk::prelude::GtkWindowExt::screen(window.as_ref()).ok_or("Failed to get screen")?;
-        let visual = screen.rgba_visual().ok_or("Failed to get RGBA visual")?;
-        window.set_visual(Some(&visual));
+            let get_image_cookie = self.connection.get_image(
+                xproto::ImageFormat::Z_PIXMAP,
+                self.root,
+                selection.x,
+                selection.y,
+                selection.w,
+                selection.h,
+                std::u32::MAX,
+            )?;
 
-        // Set up a drawing area for region selection
-        let drawing_area = gtk::DrawingArea::new();
-        drawing_area.set_size_request(
-            get_geometry_reply.width as i32,
-            get_geometry_reply.height as i32,
-        );
-        window.add(&drawing_area);
+            let mut data = get_image_cookie.reply()?.data;
+            bgr_to_rgb(&mut data);
 
-        let area_clone = drawing_area.clone();
+            Ok(data)
+        }
 
-        let drag_data = Arc::new(RwLock::new(DragData {
-            start_pos: (0, 0),
-            end_pos: (0, 0),
-            dragging: false,
-            success: false,
-        }));
+        // this function should dim the screen, then give the user a drag cursor to select a region.
+        // With a monitor the overlay only covers that monitor, so the selection can't cross onto the others.
+        pub fn select_region(
+            &self,
+            freeze: bool,
+            monitor: Option<&MonitorSelector>,
+        ) -> Result<RegionSelection, Box<dyn std::error::Error>> {
+            use gtk::prelude::*;
 
-        let mut pixbuf: Arc<Option<gdk_pixbuf::Pixbuf>> = Arc::new(None);
+            // the area the overlay covers, the whole screen unless constrained to a monitor
+            let area: RegionSelection = match monitor {
+                Some(selector) => (&self.find_monitor(selector)?).into(),
+                None => self.screen_region()?,
+            };
 
-        if freeze {
-            let (data, (w, h)) = capture_screen()?;
+            let (window, drawing_area) = self.overlay(&area, monitor.is_some())?;
 
-            let data = crate::screenshot::as_png(data, w, h)?;
+            let area_clone = drawing_area.clone();
 
-            let loader = PixbufLoader::new();
-            loader.write(&data)?;
-            loader.close()?;
+            let drag_data = Arc::new(RwLock::new(DragData {
+                start_pos: (0, 0),
+                end_pos: (0, 0),
+                dragging: false,
+                success: false,
+            }));
 
-            pixbuf = Arc::new(loader.pixbuf());
-        }
+            let mut pixbuf: Arc<Option<gdk_pixbuf::Pixbuf>> = Arc::new(None);
 
-        let drag_data_ref = Arc::clone(&drag_data);
-        let pixbuf_ref = Arc::clone(&pixbuf);
-        drawing_area.connect_draw(move |_, cr| {
-            // if freeze, draw the frozen screen
             if freeze {
-                cr.set_source_pixbuf(&pixbuf_ref.as_ref().clone().unwrap(), 0.0, 0.0);
-                if let Err(e) = cr.paint() {
-                    tracing::error!("Error painting freeze screen: {}", e);
-                }
-            }
+                let data = self.capture_region(&area)?;
 
-            let drag_data = drag_data_ref.read().unwrap();
+                let data = crate::screenshot::as_png(data, area.w, area.h)?;
 
-            // Draw the selection rectangle
-            if drag_data.dragging {
-                let (x, y, width, height) = (
-                    i16::min(drag_data.start_pos.0, drag_data.end_pos.0),
-                    i16::min(drag_data.start_pos.1, drag_data.end_pos.1),
-                    (drag_data.start_pos.0 - drag_data.end_pos.0).abs() as u16,
-                    (drag_data.start_pos.1 - drag_data.end_pos.1).abs() as u16,
-                );
+                let loader = PixbufLoader::new();
+                loader.write(&data)?;
+                loader.close()?;
 
-                cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
-                cr.rectangle(x as f64, y as f64, width as f64, height as f64);
-                cr.set_line_width(1.0);
-                cr.set_dash(&[6.0, 2.0], 0.0);
+                pixbuf = Arc::new(loader.pixbuf());
+            }
 
-                if let Err(e) = cr.stroke() {
-                    tracing::error!("Error drawing selection rectangle: {}", e);
+            let drag_data_ref = Arc::clone(&drag_data);
+            let pixbuf_ref = Arc::clone(&pixbuf);
+            drawing_area.connect_draw(move |_, cr| {
+                // if freeze, draw the frozen screen
+                if freeze {
+                    cr.set_source_pixbuf(&pixbuf_ref.as_ref().clone().unwrap(), 0.0, 0.0);
+                    if let Err(e) = cr.paint() {
+                        tracing::error!("Error painting freeze screen: {}", e);
+                    }
                 }
-            }
 
-            Inhibit(false)
-        });
+                let drag_data = drag_data_ref.read().unwrap();
+
+                // Draw the selection rectangle
+                if drag_data.dragging {
+                    let (x, y, width, height) = (
+                        i16::min(drag_data.start_pos.0, drag_data.end_pos.0),
+                        i16::min(drag_data.start_pos.1, drag_data.end_pos.1),
+                        (drag_data.start_pos.0 - drag_data.end_pos.0).abs() as u16,
+                        (drag_data.start_pos.1 - drag_data.end_pos.1).abs() as u16,
+                    );
+
+                    cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
+                    cr.rectangle(x as f64, y as f64, width as f64, height as f64);
+                    cr.set_line_width(1.0);
+                    cr.set_dash(&[6.0, 2.0], 0.0);
+
+                    if let Err(e) = cr.stroke() {
+                        tracing::error!("Error drawing selection rectangle: {}", e);
+                    }
+                }
+
+                Inhibit(false)
+            });
 
-        let drag_data_ref = Arc::clone(&drag_data);
-        let window_ref = window.clone();
-        drawing_area.connect_button_press_event(move |_, event| {
-            let mut drag_data = drag_data_ref.write().unwrap();
+            let drag_data_ref = Arc::clone(&drag_data);
+            drawing_area.connect_button_press_event(move |_, event| {
+                let mut drag_data = drag_data_ref.write().unwrap();
 
-            match event.button() {
-                1 => {
+                if event.button() == 1 {
                     drag_data.dragging = true;
                     drag_data.start_pos = (event.position().0 as i16, event.position().1 as i16);
                 }
-                3 => {
+
+                Inhibit(false)
+            });
+
+            let drag_data_ref = Arc::clone(&drag_data);
+            let window_ref = window.clone();
+            drawing_area.connect_button_release_event(move |_, event| {
+                let mut drag_data = drag_data_ref.write().unwrap();
+
+                if event.button() == 1 && drag_data.dragging {
+                    // Left button released, stop dragging
+                    drag_data.dragging = false;
+                    drag_data.end_pos = (event.position().0 as i16, event.position().1 as i16);
+                    drag_data.success = true;
                     window_ref.hide();
                     gtk::main_quit();
                 }
-                _ => {}
+                Inhibit(false)
+            });
+
+            let drag_data_ref = Arc::clone(&drag_data);
+            drawing_area.connect_motion_notify_event(move |_, event| {
+                let mut drag_data = drag_data_ref.write().unwrap();
+
+                if drag_data.dragging {
+                    // Update the selection as the user drags
+                    drag_data.end_pos = (event.position().0 as i16, event.position().1 as i16);
+                    area_clone.queue_draw();
+                }
+                Inhibit(false)
+            });
+
+            window.show_all();
+
+            gtk::main();
+
+            // Calculate the coordinates and dimensions of the selected region
+            let drag_data = drag_data.read().unwrap();
+            if !drag_data.success {
+                return Err(super::Cancelled.into());
             }
 
-            Inhibit(false)
-        });
+            let (x, y, w, h) = (
+                i16::min(drag_data.start_pos.0, drag_data.end_pos.0),
+                i16::min(drag_data.start_pos.1, drag_data.end_pos.1),
+                (drag_data.start_pos.0 - drag_data.end_pos.0).abs() as u16,
+                (drag_data.start_pos.1 - drag_data.end_pos.1).abs() as u16,
+            );
+
+            // the overlay's coordinates start at the corner of the area it covers
+            Ok(RegionSelection {
+                x: x + area.x,
+                y: y + area.y,
+                w,
+                h,
+                pixbuf: pixbuf.as_ref().clone(),
+                pixbuf_origin: (area.x, area.y),
+            })
+        }
 
-        let window_ref = window.clone();
-        drawing_area.connect_key_press_event(move |_, event| {
-            if event.keyval() == gdk::keys::constants::Escape {
-                window_ref.hide();
-                gtk::main_quit();
+        // A borderless window covering the area, for the region selection and window picking to draw on.
+        // Right click or Escape closes it, leaving whatever was being picked unset.
+        fn overlay(
+            &self,
+            area: &RegionSelection,
+            constrained: bool,
+        ) -> Result<(Arc<gtk::Window>, gtk::DrawingArea), Box<dyn std::error::Error>> {
+            use gdk::Cursor;
+            use gtk::prelude::*;
+            use gtk::{WindowPosition, WindowType};
+
+            gtk::init()?;
+
+            // the overlay goes on the session's display, which isn't necessarily GTK's default one
+            let display = match self.display {
+                Some(ref name) => gdk::Display::open(name)
+                    .ok_or_else(|| format!("Failed to open display {}", name))?,
+                None => gdk::Display::default().ok_or("Failed to get default display")?,
+            };
+
+            // Create a GTK window with transparent background
+            let window = Arc::new(gtk::Window::new(WindowType::Toplevel));
+            window.set_type_hint(gdk::WindowTypeHint::Dock);
+            window.set_decorated(false);
+            window.set_skip_taskbar_hint(true);
+            window.set_skip_pager_hint(true);
+            window.set_screen(&display.default_screen());
+            if constrained {
+                window.set_position(WindowPosition::None);
+                window.move_(area.x as i32, area.y as i32);
+            } else {
+                window.set_position(WindowPosition::CenterAlways);
             }
+            window.set_app_paintable(true);
 
-            Inhibit(false)
-        });
+            let screen = gtk::prelude::GtkWindowExt::screen(window.as_ref())
+                .ok_or("Failed to get screen")?;
+            let visual = screen.rgba_visual().ok_or("Failed to get RGBA visual")?;
+            window.set_visual(Some(&visual));
+
+            let drawing_area = gtk::DrawingArea::new();
+            drawing_area.set_size_request(area.w as i32, area.h as i32);
+            window.add(&drawing_area);
+
+            let window_ref = window.clone();
+            drawing_area.connect_button_press_event(move |_, event| {
+                if event.button() == 3 {
+                    window_ref.hide();
+                    gtk::main_quit();
+                }
+
+                Inhibit(false)
+            });
+
+            let window_ref = window.clone();
+            drawing_area.connect_key_press_event(move |_, event| {
+                if event.keyval() == gdk::keys::constants::Escape {
+                    window_ref.hide();
+                    gtk::main_quit();
+                }
+
+                Inhibit(false)
+            });
 
-        let drag_data_ref = Arc::clone(&drag_data);
-        let window_ref = window.clone();
-        drawing_area.connect_button_release_event(move |_, event| {
-            let mut drag_data = drag_data_ref.write().unwrap();
+            drawing_area.add_events(
+                gdk::EventMask::BUTTON_PRESS_MASK
+                    | gdk::EventMask::BUTTON_RELEASE_MASK
+                    | gdk::EventMask::POINTER_MOTION_MASK
+                    | gdk::EventMask::KEY_PRESS_MASK,
+            );
+
+            // Set up a transparent cursor for the drawing area
+            let cursor = Cursor::for_display(&display, CursorType::Cross);
+            drawing_area.realize();
+            drawing_area
+                .window()
+                .ok_or("Failed to get drawing area window")?
+                .set_cursor(cursor.as_ref());
+
+            Ok((window, drawing_area))
+        }
 
-            if event.button() == 1 && drag_data.dragging {
-                // Left button released, stop dragging
-                drag_data.dragging = false;
-                drag_data.end_pos = (event.position().0 as i16, event.position().1 as i16);
-                drag_data.success = true;
-                window_ref.hide();
-                gtk::main_quit();
+        // The mapped top-level windows and where they are, bottom to top
+        fn visible_windows(&self) -> Result<Vec<(Window, RegionSelection)>, ReplyOrIdError> {
+            let mut windows = Vec::new();
+
+            for window in self.get_windows()? {
+                // windows may disappear at any time, those are simply left out
+                let attributes = match self.connection.get_window_attributes(window)?.reply() {
+                    Ok(attributes) => attributes,
+                    Err(_) => continue,
+                };
+
+                if attributes.map_state != xproto::MapState::VIEWABLE
+                    || attributes.class == xproto::WindowClass::INPUT_ONLY
+                {
+                    continue;
+                }
+
+                if let Ok(geometry) = self.connection.get_geometry(window)?.reply() {
+                    windows.push((window, geometry.into()));
+                }
             }
-            Inhibit(false)
-        });
 
-        let drag_data_ref = Arc::clone(&drag_data);
-        drawing_area.connect_motion_notify_event(move |_, event| {
-            let mut drag_data = drag_data_ref.write().unwrap();
+            Ok(windows)
+        }
+
+        // Highlights the top-level window under the pointer, and returns the one clicked on
+        pub fn pick_window(&self) -> Result<Window, Box<dyn std::error::Error>> {
+            use gtk::prelude::*;
+
+            let area = self.screen_region()?;
+
+            // taken before the overlay covers everything, topmost first
+            let mut windows = self.visible_windows()?;
+            windows.reverse();
+            let windows = Rc::new(windows);
+
+            let (window, drawing_area) = self.overlay(&area, false)?;
+
+            let hovered: Arc<RwLock<Option<usize>>> = Arc::new(RwLock::new(None));
+            let picked: Arc<RwLock<Option<Window>>> = Arc::new(RwLock::new(None));
+
+            let windows_ref = Rc::clone(&windows);
+            let hovered_ref = Arc::clone(&hovered);
+            drawing_area.connect_draw(move |_, cr| {
+                if let Some(index) = *hovered_ref.read().unwrap() {
+                    let rect = &windows_ref[index].1;
+
+                    cr.rectangle(
+                        (rect.x - area.x) as f64,
+                        (rect.y - area.y) as f64,
+                        rect.w as f64,
+                        rect.h as f64,
+                    );
+                    cr.set_source_rgba(0.2, 0.5, 1.0, 0.25);
+                    if let Err(e) = cr.fill_preserve() {
+                        tracing::error!("Error highlighting window: {}", e);
+                    }
+
+                    cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
+                    cr.set_line_width(2.0);
+                    if let Err(e) = cr.stroke() {
+                        tracing::error!("Error highlighting window: {}", e);
+                    }
+                }
+
+                Inhibit(false)
+            });
+
+            let windows_ref = Rc::clone(&windows);
+            let hovered_ref = Arc::clone(&hovered);
+            drawing_area.connect_motion_notify_event(move |drawing_area, event| {
+                let (x, y) = event.root();
+
+                let index = windows_ref.iter().position(|(_, rect)| {
+                    x >= rect.x as f64
+                        && y >= rect.y as f64
+                        && x < rect.x as f64 + rect.w as f64
+                        && y < rect.y as f64 + rect.h as f64
+                });
+
+                let mut hovered = hovered_ref.write().unwrap();
+                if *hovered != index {
+                    *hovered = index;
+                    drawing_area.queue_draw();
+                }
+
+                Inhibit(false)
+            });
+
+            let windows_ref = Rc::clone(&windows);
+            let hovered_ref = Arc::clone(&hovered);
+            let picked_ref = Arc::clone(&picked);
+            let window_ref = window.clone();
+            drawing_area.connect_button_press_event(move |_, event| {
+                if event.button() == 1 {
+                    if let Some(index) = *hovered_ref.read().unwrap() {
+                        *picked_ref.write().unwrap() = Some(windows_ref[index].0);
+                        window_ref.hide();
+                        gtk::main_quit();
+                    }
+                }
+
+                Inhibit(false)
+            });
 
-            if drag_data.dragging {
-                // Update the selection as the user drags
-                drag_data.end_pos = (event.position().0 as i16, event.position().1 as i16);
-                area_clone.queue_draw();
+            window.show_all();
+
+            gtk::main();
+
+            let picked = *picked.read().unwrap();
+            picked.ok_or_else(|| super::Cancelled.into())
+        }
+
+        // The child of the root containing the window, which is its frame when the window manager reparents it
+        pub fn top_level(&self, mut window: Window) -> Result<Window, ReplyOrIdError> {
+            loop {
+                let tree = self.connection.query_tree(window)?.reply()?;
+
+                if tree.parent == self.root || tree.parent == x11rb::NONE {
+                    return Ok(window);
+                }
+
+                window = tree.parent;
+            }
+        }
+
+        // The client window a window belongs to, the one the window manager reparented. That's the window itself or
+        // an ancestor for e.g. a focused child, or the client inside it for a frame. Without a window manager that
+        // sets _NET_CLIENT_LIST_STACKING, it's the top-level window.
+        pub fn client_window(&self, window: Window) -> Result<Window, ReplyOrIdError> {
+            let clients = self
+                .get_property32(
+                    self.root,
+                    self.atoms._NET_CLIENT_LIST_STACKING,
+                    xproto::AtomEnum::WINDOW,
+                )?
+                .unwrap_or_default();
+
+            let mut current = window;
+            while current != self.root && current != x11rb::NONE {
+                if clients.contains(&current) {
+                    return Ok(current);
+                }
+
+                current = self.connection.query_tree(current)?.reply()?.parent;
             }
-            Inhibit(false)
-        });
 
-        drawing_area.add_events(
-            gdk::EventMask::BUTTON_PRESS_MASK
-                | gdk::EventMask::BUTTON_RELEASE_MASK
-                | gdk::EventMask::POINTER_MOTION_MASK
-                | gdk::EventMask::KEY_PRESS_MASK,
-        );
+            let top_level = self.top_level(window)?;
+            for client in clients {
+                if self.top_level(client).ok() == Some(top_level) {
+                    return Ok(client);
+                }
+            }
 
-        // Set up a transparent cursor for the drawing area
-        let display = gdk::Display::default().ok_or("Failed to get default display")?;
-        let cursor = Cursor::for_display(&display, CursorType::Cross);
-        drawing_area.realize();
-        drawing_area
-            .window()
-            .ok_or("Failed to get drawing area window")?
-            .set_cursor(cursor.as_ref());
+            Ok(top_level)
+        }
 
-        window.show_all();
+        // The focused window, None if nothing or the root has the focus
+        pub fn get_active_window_id(&self) -> Result<Option<Window>, ReplyOrIdError> {
+            let reply = self.connection.get_input_focus()?.reply()?;
 
-        gtk::main();
+            if reply.focus == u32::from(xproto::InputFocus::NONE)
+                || reply.focus == u32::from(xproto::InputFocus::POINTER_ROOT)
+                || reply.focus == self.root
+            {
+                return Ok(None);
+            }
 
-        // Calculate the coordinates and dimensions of the selected region
-        let drag_data = drag_data.read().unwrap();
-        if !drag_data.success {
-            return Err("User cancelled region selection".into());
+            Ok(Some(reply.focus))
         }
 
-        let (x, y, w, h) = (
-            i16::min(drag_data.start_pos.0, drag_data.end_pos.0),
-            i16::min(drag_data.start_pos.1, drag_data.end_pos.1),
-            (drag_data.start_pos.0 - drag_data.end_pos.0).abs() as u16,
-            (drag_data.start_pos.1 - drag_data.end_pos.1).abs() as u16,
-        );
+        // The window's title, preferring the UTF-8 _NET_WM_NAME over the legacy WM_NAME
+        pub fn get_window_name(&self, window: Window) -> Result<Option<String>, ReplyOrIdError> {
+            let reply = self
+                .connection
+                .get_property(
+                    false,
+                    window,
+                    self.atoms._NET_WM_NAME,
+                    self.atoms.UTF8_STRING,
+                    0,
+                    u32::MAX,
+                )?
+                .reply()?;
 
-        Ok(RegionSelection {
-            x,
-            y,
-            w,
-            h,
-            pixbuf: Some(pixbuf.as_ref().clone().unwrap()),
-        })
-    }
+            if !reply.value.is_empty() {
+                return Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()));
+            }
 
-    pub fn get_active_window_id() -> Result<Option<Window>, ReplyOrIdError> {
-        let (connection, _screen_num) =
-            RustConnection::connect(None).expect("Failed to connect to X server");
+            let reply = self
+                .connection
+                .get_property(
+                    false,
+                    window,
+                    xproto::AtomEnum::WM_NAME,
+                    xproto::AtomEnum::STRING,
+                    0,
+                    u32::MAX,
+                )?
+                .reply()?;
 
-        let get_input_focus_cookie = connection.get_input_focus()?;
-        let reply = get_input_focus_cookie.reply()?;
+            if reply.value.is_empty() {
+                return Ok(None);
+            }
 
-        Ok(Some(reply.focus))
-    }
+            Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()))
+        }
 
-    pub fn get_window_name(
-        connection: &RustConnection,
-        window: Window,
-    ) -> Result<Option<String>, ReplyOrIdError> {
-        let atom_name = CString::new("WM_NAME").expect("Failed to create CString");
+        // Where the window is on the screen, cut down to the part that's on it, as X can't capture the rest
+        pub fn window_region(
+            &self,
+            window: Window,
+            frame: bool,
+        ) -> Result<RegionSelection, Box<dyn std::error::Error>> {
+            let (left, top, right, bottom) = self.window_bounds(window, frame)?;
+
+            let screen = self.screen_region()?;
+            let (left, top) = (left.max(0), top.max(0));
+            let right = right.min(screen.w as i32);
+            let bottom = bottom.min(screen.h as i32);
+
+            if right <= left || bottom <= top {
+                return Err(format!("Window {:#x} is off the screen", window).into());
+            }
 
-        let intern_atom_cookie = connection.intern_atom(false, atom_name.as_bytes())?;
-        let intern_atom_reply: InternAtomReply = intern_atom_cookie.reply()?;
+            Ok(RegionSelection {
+                x: left as i16,
+                y: top as i16,
+                w: (right - left) as u16,
+                h: (bottom - top) as u16,
+                pixbuf: None,
+                pixbuf_origin: (0, 0),
+            })
+        }
 
-        let cookie = connection.get_property(
-            false,
-            window,
-            intern_atom_reply.atom,
-            xproto::AtomEnum::STRING,
-            0,
-            std::u32::MAX,
-        )?;
-        let reply = cookie.reply()?;
+        // The window's left, top, right and bottom edges relative to the root, wherever they are. With frame, they
+        // are moved out by the _NET_FRAME_EXTENTS the window manager sets on client windows.
+        fn window_bounds(
+            &self,
+            window: Window,
+            frame: bool,
+        ) -> Result<(i32, i32, i32, i32), ReplyOrIdError> {
+            let region = self.root_geometry(window)?;
+            let (mut left, mut top) = (region.x as i32, region.y as i32);
+            let (mut right, mut bottom) = (left + region.w as i32, top + region.h as i32);
+
+            if frame {
+                let extents = self.get_property32(
+                    window,
+                    self.atoms._NET_FRAME_EXTENTS,
+                    xproto::AtomEnum::CARDINAL,
+                )?;
+
+                if let Some([l, r, t, b]) = extents.as_deref() {
+                    left -= *l as i32;
+                    right += *r as i32;
+                    top -= *t as i32;
+                    bottom += *b as i32;
+                }
+            }
 
-        let property_data = reply.value.to_vec();
-        let window_name = String::from_utf8_lossy(&property_data).into_owned();
+            Ok((left, top, right, bottom))
+        }
 
-        Ok(Some(window_name))
-    }
+        // Whether a compositing manager is running, only then do windows keep contents of their own where they're
+        // covered or off the screen
+        fn compositing(&self) -> Result<bool, ReplyOrIdError> {
+            if self
+                .connection
+                .extension_information(composite::X11_EXTENSION_NAME)?
+                .is_none()
+            {
+                return Ok(false);
+            }
+
+            // NameWindowPixmap was added in 0.2
+            let version = self.connection.composite_query_version(0, 2)?.reply()?;
+            if (version.major_version, version.minor_version) < (0, 2) {
+                return Ok(false);
+            }
 
-    // Function to capture a specific window by its ID
-    pub fn capture_window(window_id: Window) -> Result<(Vec<u8>, (u16, u16)), ReplyOrIdError> {
-        let (connection, _screen_num) =
-            RustConnection::connect(None).expect("Failed to connect to X server");
+            let name = format!("_NET_WM_CM_S{}", self.screen_num);
+            let atom = self
+                .connection
+                .intern_atom(false, name.as_bytes())?
+                .reply()?
+                .atom;
 
-        let get_geometry_cookie = connection.get_geometry(window_id)?;
-        let get_geometry_reply = get_geometry_cookie.reply()?;
+            Ok(self.connection.get_selection_owner(atom)?.reply()?.owner != x11rb::NONE)
+        }
 
-        let capture = capture_region(&get_geometry_reply.into())?;
+        // The window's own contents through XComposite, even where other windows cover it or it's off the screen,
+        // with the alpha of ARGB windows kept. None without a compositing manager, then only what's on the screen
+        // can be captured.
+        pub fn capture_composited(
+            &self,
+            window: Window,
+            frame: bool,
+            cursor: bool,
+        ) -> Result<Option<image::RgbaImage>, Box<dyn std::error::Error>> {
+            if !self.compositing()? {
+                return Ok(None);
+            }
 
-        Ok((
-            capture,
-            (get_geometry_reply.width, get_geometry_reply.height),
-        ))
-    }
+            // only top-level windows are redirected, a client's contents are part of its frame's pixmap
+            let top_level = self.top_level(window)?;
+            let outer = self.root_geometry(top_level)?;
+            let border = self
+                .connection
+                .get_geometry(top_level)?
+                .reply()?
+                .border_width as i32;
+
+            // the pixmap includes the border, so it starts that much further up and left
+            let origin = (outer.x as i32 - border, outer.y as i32 - border);
+            let size = (outer.w as i32 + 2 * border, outer.h as i32 + 2 * border);
+
+            let (left, top, right, bottom) = self.window_bounds(window, frame)?;
+            let (left, top) = ((left - origin.0).max(0), (top - origin.1).max(0));
+            let (right, bottom) = (
+                (right - origin.0).min(size.0),
+                (bottom - origin.1).min(size.1),
+            );
 
-    // Function to capture the whole screen
-    pub fn capture_screen() -> Result<(Vec<u8>, (u16, u16)), ReplyOrIdError> {
-        let (connection, _screen_num) =
-            RustConnection::connect(None).expect("Failed to connect to X server");
-        let root_window = get_root_window(&connection)?;
+            if right <= left || bottom <= top {
+                return Err(format!("Window {:#x} is outside of its frame", window).into());
+            }
+
+            let (w, h) = ((right - left) as u16, (bottom - top) as u16);
+
+            let pixmap = self.connection.generate_id()?;
+            self.connection
+                .composite_name_window_pixmap(top_level, pixmap)?
+                .check()?;
+
+            let reply = self
+                .connection
+                .get_image(
+                    xproto::ImageFormat::Z_PIXMAP,
+                    pixmap,
+                    left as i16,
+                    top as i16,
+                    w,
+                    h,
+                    u32::MAX,
+                )
+                .map(|cookie| cookie.reply());
+            // freed before looking at the reply, so a failed capture doesn't leak it
+            self.connection.free_pixmap(pixmap)?;
+            let reply = reply??;
+
+            if reply.data.len() != w as usize * h as usize * 4 {
+                return Err(format!("Unsupported pixel format, depth {}", reply.depth).into());
+            }
+
+            let mut data = reply.data;
+            bgr_to_rgb(&mut data);
+
+            for pixel in data.chunks_exact_mut(4) {
+                match (reply.depth, pixel[3]) {
+                    // anything but 32 bits has no alpha, the byte is just padding
+                    (depth, _) if depth != 32 => pixel[3] = 255,
+                    (_, 0) | (_, 255) => {}
+                    // X premultiplies the colours by the alpha, PNG wants them as they are
+                    (_, alpha) => {
+                        for c in &mut pixel[..3] {
+                            *c = (*c as u32 * 255 / alpha as u32).min(255) as u8;
+                        }
+                    }
+                }
+            }
 
-        let capture = capture_window(root_window)?;
+            if cursor {
+                let region = RegionSelection {
+                    x: (origin.0 + left) as i16,
+                    y: (origin.1 + top) as i16,
+                    w,
+                    h,
+                    pixbuf: None,
+                    pixbuf_origin: (0, 0),
+                };
+
+                self.draw_cursor(&mut data, &region);
+            }
 
-        Ok(capture)
+            Ok(image::RgbaImage::from_raw(w as u32, h as u32, data))
+        }
+
+        // Draws the pointer over a capture of the region, as RGBA. Leaves the capture as it is if XFixes, which
+        // hands out the cursor's image, isn't available.
+        pub fn draw_cursor(&self, data: &mut [u8], region: &RegionSelection) {
+            let cursor = match self.get_cursor_image() {
+                Ok(cursor) => cursor,
+                Err(e) => {
+                    tracing::warn!("Failed to get the cursor: {}", e);
+                    return;
+                }
+            };
+
+            // the image's corner, the position is where the hotspot is
+            let left = cursor.x as i32 - cursor.xhot as i32 - region.x as i32;
+            let top = cursor.y as i32 - cursor.yhot as i32 - region.y as i32;
+
+            for row in 0..cursor.height as i32 {
+                for col in 0..cursor.width as i32 {
+                    let (x, y) = (left + col, top + row);
+                    if x < 0 || y < 0 || x >= region.w as i32 || y >= region.h as i32 {
+                        continue;
+                    }
+
+                    // ARGB, with the colours already multiplied by the alpha
+                    let argb = cursor.cursor_image[(row * cursor.width as i32 + col) as usize];
+                    let alpha = argb >> 24;
+                    if alpha == 0 {
+                        continue;
+                    }
+
+                    let source = [(argb >> 16) & 0xff, (argb >> 8) & 0xff, argb & 0xff, alpha];
+                    let i = (y as usize * region.w as usize + x as usize) * 4;
+
+                    for (d, s) in data[i..i + 4].iter_mut().zip(source) {
+                        *d = (s + *d as u32 * (255 - alpha) / 255) as u8;
+                    }
+                }
+            }
+        }
+
+        fn get_cursor_image(
+            &self,
+        ) -> Result<xfixes::GetCursorImageReply, Box<dyn std::error::Error>> {
+            if self
+                .connection
+                .extension_information(xfixes::X11_EXTENSION_NAME)?
+                .is_none()
+            {
+                return Err("The X server doesn't support XFixes".into());
+            }
+
+            // the version has to be negotiated before any other XFixes request
+            self.connection.xfixes_query_version(4, 0)?.reply()?;
+
+            Ok(self.connection.xfixes_get_cursor_image()?.reply()?)
+        }
+
+        // Function to capture the whole screen
+        pub fn capture_screen(&self) -> Result<(Vec<u8>, (u16, u16)), ReplyOrIdError> {
+            let region = self.screen_region()?;
+
+            let capture = self.capture_region(&region)?;
+
+            Ok((capture, (region.w, region.h)))
+        }
     }
 }
 
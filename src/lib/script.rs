@@ -0,0 +1,198 @@
+#[cfg(feature = "scripting")]
+mod rhai_script {
+    use rhai::{Blob, Dynamic, Engine, EvalAltResult, Map, Scope};
+
+    use crate::{config::ScriptUploader, upload::UploadResult};
+
+    // Scripts are sandboxed, they can't import modules or touch the filesystem, and the only way out is the
+    // functions registered here:
+    //
+    //   http(method, url, headers, body) -> #{ status, body, headers }
+    //     body can be a string or a blob, headers is a map of header names to values
+    //   http_multipart(method, url, headers, fields, file_field, data, filename) -> #{ status, body, headers }
+    //     fields is a map of text parts sent along with the file
+    //
+    // print and debug go to the log. parse_json and to_json are available from the standard library.
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+
+        engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+        engine.set_max_operations(50_000_000);
+        engine.set_max_call_levels(64);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(256 * 1024 * 1024);
+        engine.set_max_array_size(1_000_000);
+        engine.set_max_map_size(100_000);
+
+        engine.on_print(|s| tracing::info!("script: {}", s));
+        engine.on_debug(|s, _, pos| tracing::debug!("script {}: {}", pos, s));
+
+        let client = reqwest::blocking::Client::new();
+
+        let c = client.clone();
+        engine.register_fn(
+            "http",
+            move |method: &str, url: &str, headers: Map, body: Blob| {
+                send(c.request(parse_method(method)?, url), headers, |r| {
+                    r.body(body)
+                })
+            },
+        );
+
+        let c = client.clone();
+        engine.register_fn(
+            "http",
+            move |method: &str, url: &str, headers: Map, body: &str| {
+                let body = body.to_string();
+                send(c.request(parse_method(method)?, url), headers, |r| {
+                    r.body(body)
+                })
+            },
+        );
+
+        let c = client;
+        engine.register_fn(
+            "http_multipart",
+            move |method: &str,
+                  url: &str,
+                  headers: Map,
+                  fields: Map,
+                  file_field: &str,
+                  data: Blob,
+                  filename: &str| {
+                let mut form = reqwest::blocking::multipart::Form::new().part(
+                    file_field.to_string(),
+                    reqwest::blocking::multipart::Part::bytes(data).file_name(filename.to_string()),
+                );
+
+                for (k, v) in fields {
+                    form = form.text(k.to_string(), v.to_string());
+                }
+
+                send(c.request(parse_method(method)?, url), headers, |r| {
+                    r.multipart(form)
+                })
+            },
+        );
+
+        engine
+    }
+
+    fn parse_method(method: &str) -> Result<reqwest::Method, Box<EvalAltResult>> {
+        reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|e| format!("Invalid HTTP method {}: {}", method, e).into())
+    }
+
+    fn send(
+        req: reqwest::blocking::RequestBuilder,
+        headers: Map,
+        with_body: impl FnOnce(reqwest::blocking::RequestBuilder) -> reqwest::blocking::RequestBuilder,
+    ) -> Result<Map, Box<EvalAltResult>> {
+        let mut req = req;
+        for (k, v) in headers {
+            req = req.header(k.as_str(), v.to_string());
+        }
+
+        let res = with_body(req).send().map_err(|e| e.to_string())?;
+
+        let mut response_headers = Map::new();
+        for (k, v) in res.headers() {
+            response_headers.insert(
+                k.as_str().into(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned().into(),
+            );
+        }
+
+        let mut response = Map::new();
+        response.insert("status".into(), (res.status().as_u16() as i64).into());
+        response.insert("headers".into(), response_headers.into());
+        response.insert("body".into(), res.text().map_err(|e| e.to_string())?.into());
+
+        Ok(response)
+    }
+
+    // Runs the uploader's script with data, format, filename and uploader_name in scope.
+    // The script should evaluate to a map with any of url, thumbnail_url, deletion_url and error_message.
+    pub fn upload(
+        u: &ScriptUploader,
+        data: &[u8],
+        format: &str,
+        filename: &str,
+    ) -> Result<UploadResult, Box<dyn std::error::Error>> {
+        let script = std::fs::read_to_string(&u.path)?;
+
+        let mut scope = Scope::new();
+        scope.push("data", data.to_vec());
+        scope.push("format", format.to_string());
+        scope.push("filename", filename.to_string());
+        scope.push("uploader_name", u.name.clone());
+
+        let result: Map = engine().eval_with_scope(&mut scope, &script)?;
+
+        Ok(to_upload_result(&u.name, result))
+    }
+
+    // Runs a script in place of the custom syntax fields, with the response body in scope as response.
+    // The script should evaluate to a map like the one returned by upload scripts.
+    pub fn parse_response(
+        path: &str,
+        uploader_name: &str,
+        response: &str,
+    ) -> Result<UploadResult, Box<dyn std::error::Error>> {
+        let script = std::fs::read_to_string(path)?;
+
+        let mut scope = Scope::new();
+        scope.push("response", response.to_string());
+        scope.push("uploader_name", uploader_name.to_string());
+
+        let result: Map = engine().eval_with_scope(&mut scope, &script)?;
+
+        Ok(to_upload_result(uploader_name, result))
+    }
+
+    fn to_upload_result(uploader_name: &str, map: Map) -> UploadResult {
+        let field = |name: &str| -> Option<String> {
+            map.get(name)
+                .filter(|v| !v.is_unit())
+                .map(Dynamic::to_string)
+        };
+
+        UploadResult {
+            url: field("url"),
+            thumbnail_url: field("thumbnail_url"),
+            deletion_url: field("deletion_url"),
+            error_message: field("error_message"),
+            ..UploadResult::new(uploader_name)
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod disabled {
+    use crate::{config::ScriptUploader, upload::UploadResult};
+
+    const DISABLED: &str =
+        "Scripting not enabled. Please rebuild with the `scripting` feature to enable it.";
+
+    pub fn upload(
+        _: &ScriptUploader,
+        _: &[u8],
+        _: &str,
+        _: &str,
+    ) -> Result<UploadResult, Box<dyn std::error::Error>> {
+        Err(DISABLED.into())
+    }
+
+    pub fn parse_response(
+        _: &str,
+        _: &str,
+        _: &str,
+    ) -> Result<UploadResult, Box<dyn std::error::Error>> {
+        Err(DISABLED.into())
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+pub use disabled::*;
+#[cfg(feature = "scripting")]
+pub use rhai_script::*;
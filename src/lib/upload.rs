@@ -1,11 +1,17 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
 use serde_derive::{Deserialize, Serialize};
 
 use crate::{
-    config::{self, Config, Uploader},
-    util,
+    cache,
+    config::{self, CommandUploader, Config, HttpUploader, Uploader},
+    crypto, email, expiry, script, util,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct UploadResult {
     pub uploader_name: String,
     pub url: Option<String>,
@@ -14,6 +20,137 @@ pub struct UploadResult {
     pub error_message: Option<String>,
 
     pub file_path: Option<String>,
+
+    #[serde(default)]
+    pub album_item: Option<String>, // The id used to add this upload to an album, if the uploader has one
+
+    #[serde(default)]
+    pub duration_ms: Option<u64>, // How long the uploader took
+    #[serde(default)]
+    pub bytes: Option<usize>, // Size of the uploaded file
+    #[serde(default)]
+    pub response: Option<ResponseInfo>, // Set for uploaders that make an HTTP request themselves
+    #[serde(default)]
+    pub cached: bool, // Reused from an earlier upload of the same file rather than uploaded again
+}
+
+impl UploadResult {
+    pub fn new(uploader_name: &str) -> Self {
+        Self {
+            uploader_name: uploader_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn error(uploader_name: &str, error_message: impl Into<String>) -> Self {
+        Self {
+            error_message: Some(error_message.into()),
+            ..Self::new(uploader_name)
+        }
+    }
+}
+
+// Raw response bodies are cut off after this many bytes, they're only kept for diagnostics
+const MAX_RESPONSE_BODY: usize = 4096;
+
+// The HTTP response an upload got, kept so failed or odd uploads can be diagnosed without re-running them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResponseInfo {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    #[serde(default)]
+    pub truncated: bool, // Whether body was cut off at MAX_RESPONSE_BODY
+}
+
+impl ResponseInfo {
+    // Reads the whole response, returning its full body along with the truncated record of it
+    fn read(res: reqwest::blocking::Response) -> Result<(Self, String), reqwest::Error> {
+        let status = res.status().as_u16();
+        let headers = res
+            .headers()
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.as_str().to_string(),
+                    String::from_utf8_lossy(v.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+
+        let text = res.text()?;
+
+        let mut end = text.len().min(MAX_RESPONSE_BODY);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let info = Self {
+            status,
+            headers,
+            body: text[..end].to_string(),
+            truncated: end < text.len(),
+        };
+
+        Ok((info, text))
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+// Which of the configured uploaders an upload is sent to
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum Selection {
+    #[default]
+    All,
+    None,               // Don't upload anywhere
+    OnlySave,           // Only the File uploaders, i.e. save locally
+    Named(Vec<String>), // Only the uploaders with these names
+}
+
+impl Selection {
+    pub fn includes(&self, uploader: &Uploader) -> bool {
+        match self {
+            Self::All => true,
+            Self::None => false,
+            Self::OnlySave => matches!(uploader, Uploader::File(_)),
+            Self::Named(names) => names
+                .iter()
+                .any(|n| n.eq_ignore_ascii_case(uploader.name())),
+        }
+    }
+
+    // Fails if any of the named uploaders isn't in the config, rather than quietly uploading to fewer
+    pub fn check(&self, conf: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        if let Self::Named(names) = self {
+            for name in names {
+                if conf.uploader(name).is_none() {
+                    return Err(format!("No uploader named {} in the config", name).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Per-invocation options for an upload, as opposed to the config which applies to every upload
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UploadOptions {
+    #[serde(default)]
+    pub selection: Selection,
+    #[serde(default)]
+    pub filename: Option<String>, // Name of the file being uploaded, generated if not set
+    #[serde(default)]
+    pub window_title: Option<String>, // Title of the captured window, if the capture was of a window
+    #[serde(default)]
+    pub force: bool, // Upload even if the cache says the uploader already has this file
+    #[serde(default)]
+    pub expire: Option<u64>, // Seconds after which the uploads are deleted, overrides the uploaders' expire
+    #[serde(default)]
+    pub skip_webhooks: bool, // Leave the webhooks out, e.g. for the files of a batch, which is announced once done
 }
 
 fn deserialize_to_x_www_form_urlencoded(data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
@@ -35,169 +172,910 @@ pub fn upload(
     conf: &Config,
     data: &[u8],
     format: &str,
+    opts: &UploadOptions,
 ) -> Result<Vec<UploadResult>, Box<dyn std::error::Error>> {
     let mut results = Vec::new();
     let mut client: Option<reqwest::blocking::Client> = None;
 
-    for uploader in &conf.uploaders {
-        match uploader {
-            Uploader::HTTP(ref u) => {
-                if client.is_none() {
-                    client = Some(reqwest::blocking::Client::new());
-                }
+    opts.selection.check(conf)?;
 
-                let client = client.as_ref().unwrap();
+    let filename = opts
+        .filename
+        .clone()
+        .unwrap_or_else(|| format!("{}.{}", conf.make_filename(None), format));
 
-                let method = match u.request_method.to_uppercase().as_str() {
-                    "GET" => reqwest::Method::GET,
-                    "POST" => reqwest::Method::POST,
-                    "PUT" => reqwest::Method::PUT,
-                    "DELETE" => reqwest::Method::DELETE,
-                    "HEAD" => reqwest::Method::HEAD,
-                    "OPTIONS" => reqwest::Method::OPTIONS,
-                    "CONNECT" => reqwest::Method::CONNECT,
-                    "PATCH" => reqwest::Method::PATCH,
-                    _ => reqwest::Method::POST,
-                };
+    // webhooks only announce the other uploads, so they run after them
+    let (webhooks, uploaders): (Vec<&Uploader>, Vec<&Uploader>) = conf
+        .uploaders
+        .iter()
+        .filter(|u| opts.selection.includes(u))
+        .partition(|u| u.is_webhook());
 
-                let mut req = client.request(method, &u.request_url);
+    let hash = cache::hash(data);
 
-                if u.parameters.is_some() {
-                    req = req.query(&u.parameters.as_ref().unwrap());
-                }
+    for uploader in uploaders {
+        let ttl = match (opts.expire, uploader.expire()) {
+            (Some(_), _) if !uploader.can_expire() => {
+                tracing::info!(
+                    "{} can't delete its uploads, so it doesn't expire",
+                    uploader.name()
+                );
+                Ok(None)
+            }
+            (Some(ttl), _) => Ok(Some(ttl)),
+            (None, Some(expire)) => util::parse_duration(expire).map(Some),
+            (None, None) => Ok(None),
+        };
 
-                if u.headers.is_some() {
-                    for (k, v) in u.headers.as_ref().unwrap() {
-                        req = req.header(k, v);
-                    }
-                }
+        // fail before anything is sent, rather than leave an upload behind that's never deleted
+        let ttl = match ttl {
+            Ok(ttl) => ttl,
+            Err(e) => {
+                let result = UploadResult::error(uploader.name(), format!("Invalid expire: {}", e));
+                conf.hooks.after_upload(&result);
+                results.push(result);
+                continue;
+            }
+        };
 
-                let file_form_name = u.file_form_name.clone().unwrap_or("image".to_string());
+        // saving locally and mailing are expected to happen every time, only remote uploads are reused.
+        // An upload that expires gets its own deletion, so it neither reuses nor is reused by another upload, and
+        // an encrypted upload's link holds its key, which isn't written to disk.
+        let cacheable = matches!(
+            uploader,
+            Uploader::HTTP(_) | Uploader::Command(_) | Uploader::Script(_)
+        ) && ttl.is_none()
+            && !matches!(uploader, Uploader::HTTP(u) if u.encrypt);
 
-                match &u.body {
-                    config::Body::MultipartFormData => {
-                        let mut form = reqwest::blocking::multipart::Form::new().part(
-                            file_form_name,
-                            reqwest::blocking::multipart::Part::bytes(data.to_vec()),
-                        );
+        if cacheable && !opts.force {
+            if let Some(mut result) = cache::lookup(conf, uploader, &hash) {
+                tracing::info!(
+                    "{} already has this file, reusing the previous upload",
+                    uploader.name()
+                );
 
-                        if u.arguments.is_some() {
-                            for (k, v) in u.arguments.as_ref().unwrap() {
-                                form = form.text(k.clone(), v.clone());
-                            }
-                        }
+                // the timing and response belong to the earlier upload, not this one
+                result.duration_ms = None;
+                result.bytes = None;
+                result.response = None;
+                result.cached = true;
 
-                        req = req.multipart(form);
-                    }
+                conf.hooks.after_upload(&result);
+                results.push(result);
+                continue;
+            }
+        }
 
-                    config::Body::FormURLEncoded => {
-                        let url_encoded = deserialize_to_x_www_form_urlencoded(data)?;
+        let started = Instant::now();
 
-                        req = req
-                            .header("Content-Type", "application/x-www-form-urlencoded")
-                            .body(url_encoded);
-                    }
+        // one uploader failing shouldn't lose the others, so its error is reported in its result
+        let mut result = upload_to(conf, &mut client, uploader, data, format, &filename, opts)
+            .unwrap_or_else(|e| UploadResult::error(uploader.name(), e.to_string()));
 
-                    config::Body::JSON => {
-                        let json = serde_json::json!({
-                            file_form_name: std::str::from_utf8(data)?
-                        });
+        result.duration_ms = Some(started.elapsed().as_millis() as u64);
+        result.bytes.get_or_insert(data.len());
 
-                        req = req.json(&json);
-                    }
+        if cacheable {
+            cache::store(conf, uploader, &hash, &result);
+        }
 
-                    config::Body::XML => {
-                        let xml = format!(
-                            r#"<xml><name>{}</name><file>{}</file></xml>"#,
-                            file_form_name,
-                            std::str::from_utf8(data)?
-                        );
+        // an upload that won't be deleted when asked to counts as failed, even though it has a link
+        if let Some(ttl) = ttl {
+            if let Err(e) = expiry::schedule(&result, ttl) {
+                result.error_message = Some(format!("The upload can't be set to expire: {}", e));
+            }
+        }
 
-                        req = req.header("Content-Type", "application/xml").body(xml);
-                    }
+        conf.hooks.after_upload(&result);
 
-                    config::Body::Binary => {
-                        req = req
-                            .header("Content-Type", "application/octet-stream")
-                            .body(data.to_vec());
-                    }
+        results.push(result);
+    }
 
-                    _ => {
-                        req = req
-                            .header("Content-Type", "application/octet-stream")
-                            .body(data.to_vec());
-                    }
-                };
+    if !opts.skip_webhooks {
+        let notified = notify_webhooks(conf, &webhooks, Some(data), &results, &filename, opts);
+        results.extend(notified);
+    }
 
-                let request = req.build()?;
+    conf.hooks.after_all_uploads(&results);
 
-                let res = client.execute(request)?;
+    Ok(results)
+}
 
-                if !res.status().is_success() {
-                    results.push(UploadResult {
-                        uploader_name: u.name.clone(),
-                        url: None,
-                        thumbnail_url: None,
-                        deletion_url: None,
-                        error_message: Some(format!("{}: {}", res.status(), res.text()?)),
-                        file_path: None,
-                    });
+// Announces the first upload in results that gave a link on each of the webhooks, they're run after every other
+// uploader. $url$ is only ever a link, so when nothing was uploaded anywhere only the webhooks that attach the
+// file still post, the others are skipped. data is the file to attach, if there's a single one.
+pub fn notify_webhooks(
+    conf: &Config,
+    webhooks: &[&Uploader],
+    data: Option<&[u8]>,
+    results: &[UploadResult],
+    filename: &str,
+    opts: &UploadOptions,
+) -> Vec<UploadResult> {
+    let mut notified = Vec::new();
 
-                    continue;
-                }
+    let succeeded = || results.iter().filter(|r| r.error_message.is_none());
+    let source = match succeeded()
+        .find(|r| r.url.is_some())
+        .or_else(|| succeeded().find(|r| r.file_path.is_some()))
+    {
+        Some(source) => source,
+        None => {
+            if !webhooks.is_empty() {
+                tracing::warn!("Nothing was uploaded, skipping webhooks");
+            }
+            return notified;
+        }
+    };
 
-                let text = res.text()?;
+    // prefer the name the file was actually saved under
+    let filename = results
+        .iter()
+        .filter_map(|r| r.file_path.as_deref())
+        .filter_map(|p| std::path::Path::new(p).file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .next()
+        .unwrap_or(filename.to_string());
 
-                let (mut url, mut thumbnail_url, mut deletion_url): (String, String, String) =
-                    (String::new(), String::new(), String::new());
+    let client = reqwest::blocking::Client::new();
 
-                if !u.url.is_empty() {
-                    url = util::parse_custom_syntax(&u.url, &text)?;
-                }
+    for webhook in webhooks {
+        let u = match webhook {
+            Uploader::HTTP(ref u) => u,
+            _ => continue,
+        };
 
-                if u.thumbnail_url.is_some() {
-                    thumbnail_url =
-                        util::parse_custom_syntax(u.thumbnail_url.as_ref().unwrap(), &text)?;
-                }
+        let attaches = data.is_some() && matches!(u.body, config::Body::MultipartFormData);
+        if source.url.is_none() && !attaches {
+            tracing::warn!(
+                "Nothing was uploaded anywhere, so {} has no link to post",
+                u.name
+            );
+            continue;
+        }
 
-                if u.deletion_url.is_some() {
-                    deletion_url =
-                        util::parse_custom_syntax(u.deletion_url.as_ref().unwrap(), &text)?;
+        let result = notify_webhook(&client, u, data, source, &filename, opts)
+            .unwrap_or_else(|e| UploadResult::error(&u.name, e.to_string()));
+
+        conf.hooks.after_upload(&result);
+
+        notified.push(result);
+    }
+
+    notified
+}
+
+// Sends the data to a single uploader, without consulting the cache, scheduling expiry or running hooks.
+// client is created the first time an uploader needs one, so it can be shared between calls.
+pub fn upload_to(
+    conf: &Config,
+    client: &mut Option<reqwest::blocking::Client>,
+    uploader: &Uploader,
+    data: &[u8],
+    format: &str,
+    filename: &str,
+    opts: &UploadOptions,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let result = match uploader {
+        Uploader::HTTP(ref u) => {
+            let client = client.get_or_insert_with(reqwest::blocking::Client::new);
+
+            if u.encrypt {
+                upload_encrypted(client, conf, u, data, filename)?
+            } else {
+                upload_http(client, u, data, filename)?
+            }
+        }
+
+        Uploader::File(ref u) => {
+            let filename = format!(
+                "{}/{}.{}",
+                u.file_path,
+                conf.make_filename(Some(&u.file_name)),
+                format
+            );
+
+            std::fs::create_dir_all(&u.file_path)?;
+
+            std::fs::write(&filename, data)?;
+
+            UploadResult {
+                file_path: Some(filename),
+                ..UploadResult::new(&u.name)
+            }
+        }
+
+        Uploader::Command(ref u) => upload_command(u, data, format)?,
+
+        Uploader::Script(ref u) => script::upload(u, data, format, filename)?,
+
+        Uploader::Email(ref u) => email::send(
+            conf,
+            u,
+            data,
+            format,
+            filename,
+            opts.window_title.as_deref(),
+        )?,
+    };
+
+    Ok(result)
+}
+
+// Posts a message about an upload to a webhook. The uploader's payload is a JSON template, where $url$,
+// $thumbnail_url$, $deletion_url$, $filename$, $window_title$, $timestamp$ and $uploader$ are replaced with
+// the details of the upload, it defaults to a message containing just the URL.
+// With a MultipartFormData body the image is attached as file_form_name and the payload is sent as payload_json,
+// otherwise the payload is sent as the JSON body.
+pub fn notify_webhook(
+    client: &reqwest::blocking::Client,
+    u: &HttpUploader,
+    data: Option<&[u8]>,
+    source: &UploadResult,
+    filename: &str,
+    opts: &UploadOptions,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let payload = webhook_payload(u, source, filename, opts);
+    let req = build_webhook_request(client, u, data, filename, payload)?;
+
+    let started = Instant::now();
+    let (response, text) = ResponseInfo::read(req.send()?)?;
+
+    let error_message = if response.is_success() {
+        None
+    } else {
+        Some(format!("{}: {}", response.status, text))
+    };
+
+    Ok(UploadResult {
+        error_message,
+        duration_ms: Some(started.elapsed().as_millis() as u64),
+        response: Some(response),
+        ..UploadResult::new(&u.name)
+    })
+}
+
+// Fills in the webhook's payload template with the details of the upload
+pub fn webhook_payload(
+    u: &HttpUploader,
+    source: &UploadResult,
+    filename: &str,
+    opts: &UploadOptions,
+) -> String {
+    let template = u
+        .payload
+        .as_deref()
+        .unwrap_or(r#"{"content": "$url$", "text": "$url$"}"#);
+
+    // the values end up inside JSON strings, so they need escaping
+    let escape = |v: &str| {
+        let quoted = serde_json::Value::String(v.to_string()).to_string();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+
+    let vars = [
+        ("url", escape(source.url.as_deref().unwrap_or(""))),
+        (
+            "thumbnail_url",
+            escape(source.thumbnail_url.as_deref().unwrap_or("")),
+        ),
+        (
+            "deletion_url",
+            escape(source.deletion_url.as_deref().unwrap_or("")),
+        ),
+        ("filename", escape(filename)),
+        (
+            "window_title",
+            escape(opts.window_title.as_deref().unwrap_or("")),
+        ),
+        ("timestamp", escape(&chrono::Utc::now().to_rfc3339())),
+        ("uploader", escape(&source.uploader_name)),
+    ];
+
+    util::fill_template(template, &vars)
+}
+
+// What testing a webhook announces, since there's no real upload to link to
+pub fn webhook_test_source(u: &HttpUploader, filename: &str) -> UploadResult {
+    UploadResult {
+        url: Some(format!("https://example.com/{}", filename)),
+        ..UploadResult::new(&u.name)
+    }
+}
+
+// Builds the message notify_webhook sends, without sending it
+pub fn build_webhook_request(
+    client: &reqwest::blocking::Client,
+    u: &HttpUploader,
+    data: Option<&[u8]>,
+    filename: &str,
+    payload: String,
+) -> Result<reqwest::blocking::RequestBuilder, Box<dyn std::error::Error>> {
+    let mut req = client.request(request_method(u), &u.request_url);
+
+    if let Some(ref parameters) = u.parameters {
+        req = req.query(parameters);
+    }
+
+    if let Some(ref headers) = u.headers {
+        for (k, v) in headers {
+            req = req.header(k, v);
+        }
+    }
+
+    req = match u.body {
+        config::Body::MultipartFormData => {
+            let file_form_name = u.file_form_name.clone().unwrap_or("file".to_string());
+
+            let mut form = reqwest::blocking::multipart::Form::new();
+            if let Some(data) = data {
+                form = form.part(file_form_name, file_part(data, filename)?);
+            }
+
+            req.multipart(form.text("payload_json", payload))
+        }
+        _ => req.header("Content-Type", "application/json").body(payload),
+    };
+
+    Ok(req)
+}
+
+fn upload_http(
+    client: &reqwest::blocking::Client,
+    u: &HttpUploader,
+    data: &[u8],
+    filename: &str,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let request = build_request(client, u, data, filename)?.build()?;
+
+    let (response, text) = ResponseInfo::read(client.execute(request)?)?;
+
+    let mut result = if response.is_success() {
+        parse_response(u, &text)?
+    } else {
+        UploadResult::error(&u.name, format!("{}: {}", response.status, text))
+    };
+
+    result.response = Some(response);
+
+    Ok(result)
+}
+
+// Only the encrypted bytes are sent, the key is added to the fragment of the URL. A thumbnail made by the host
+// would be of the ciphertext, so it is dropped.
+fn upload_encrypted(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    u: &HttpUploader,
+    data: &[u8],
+    filename: &str,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let encrypted = crypto::encrypt(data)?;
+
+    let mut result = upload_http(client, u, &encrypted.data, &encrypted_filename(filename))?;
+    result.bytes = Some(encrypted.data.len());
+
+    if let Some(url) = result.url.take().filter(|url| !url.is_empty()) {
+        result.url = Some(crypto::share_url(
+            &url,
+            &encrypted.key,
+            conf.viewer_url.as_deref(),
+        )?);
+    }
+    result.thumbnail_url = None;
+
+    Ok(result)
+}
+
+// The metadata written as JSON to the stdin of a command uploader
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandRequest {
+    pub uploader_name: String,
+    pub file_path: String,
+    pub format: String,
+    pub size: usize,
+}
+
+// What a command uploader is expected to print as JSON on stdout, every field is optional
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandResponse {
+    pub url: Option<String>,
+    pub thumbnail_url: Option<String>,
+    pub deletion_url: Option<String>,
+    pub error_message: Option<String>,
+}
+
+// Runs an external program to upload the file. The file is written to a temporary path and a CommandRequest is
+// written to the program's stdin, the program should print a CommandResponse to stdout and exit with status 0.
+// Anything the program prints to stderr is logged, and reported as the error if the program fails.
+pub fn upload_command(
+    u: &CommandUploader,
+    data: &[u8],
+    format: &str,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let path = util::write_temp_file(data, format)?;
+
+    let request = CommandRequest {
+        uploader_name: u.name.clone(),
+        file_path: path.to_string_lossy().into_owned(),
+        format: format.to_string(),
+        size: data.len(),
+    };
+
+    let result = run_command(u, &request);
+
+    if let Err(e) = std::fs::remove_file(&path) {
+        tracing::warn!("Failed to remove temporary file {}: {}", path.display(), e);
+    }
+
+    let (stdout, stderr) = match result {
+        Ok(output) => output,
+        Err(e) => {
+            return Ok(UploadResult::error(&u.name, e.to_string()));
+        }
+    };
+
+    if !stderr.trim().is_empty() {
+        tracing::info!("{} stderr: {}", u.name, stderr.trim());
+    }
+
+    let response: CommandResponse = match serde_json::from_str(&stdout) {
+        Ok(response) => response,
+        Err(e) => CommandResponse {
+            error_message: Some(format!("Invalid response from {}: {}", u.path, e)),
+            ..Default::default()
+        },
+    };
+
+    Ok(UploadResult {
+        url: response.url,
+        thumbnail_url: response.thumbnail_url,
+        deletion_url: response.deletion_url,
+        error_message: response.error_message,
+        ..UploadResult::new(&u.name)
+    })
+}
+
+// Returns the stdout and stderr of the command, or an error if it couldn't be run, timed out or failed
+fn run_command(
+    u: &CommandUploader,
+    request: &CommandRequest,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let mut child = Command::new(&u.path)
+        .args(&u.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // read the pipes on their own threads so a chatty program can't fill a pipe and deadlock us while we wait
+    let mut stdout = child.stdout.take().ok_or("Failed to open stdout")?;
+    let mut stderr = child.stderr.take().ok_or("Failed to open stderr")?;
+
+    let stdout_thread = std::thread::spawn(move || {
+        let mut s = String::new();
+        stdout.read_to_string(&mut s).map(|_| s)
+    });
+    let stderr_thread = std::thread::spawn(move || {
+        let mut s = String::new();
+        stderr.read_to_string(&mut s).map(|_| s)
+    });
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // the program may exit without reading stdin, which is fine
+        if let Err(e) = stdin.write_all(&serde_json::to_vec(request)?) {
+            tracing::warn!("Failed to write to {} stdin: {}", u.path, e);
+        }
+    }
+
+    let deadline = u.timeout.map(|t| Instant::now() + Duration::from_secs(t));
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            child.kill()?;
+            child.wait()?;
+            return Err(format!("{} timed out after {}s", u.path, u.timeout.unwrap_or(0)).into());
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    let stdout = stdout_thread
+        .join()
+        .map_err(|_| "Failed to read stdout")??;
+    let stderr = stderr_thread
+        .join()
+        .map_err(|_| "Failed to read stderr")??;
+
+    if !status.success() {
+        return Err(format!("{} exited with {}: {}", u.path, status, stderr.trim()).into());
+    }
+
+    Ok((stdout, stderr))
+}
+
+fn request_method(u: &HttpUploader) -> reqwest::Method {
+    match u.request_method.to_uppercase().as_str() {
+        "GET" => reqwest::Method::GET,
+        "POST" => reqwest::Method::POST,
+        "PUT" => reqwest::Method::PUT,
+        "DELETE" => reqwest::Method::DELETE,
+        "HEAD" => reqwest::Method::HEAD,
+        "OPTIONS" => reqwest::Method::OPTIONS,
+        "CONNECT" => reqwest::Method::CONNECT,
+        "PATCH" => reqwest::Method::PATCH,
+        _ => reqwest::Method::POST,
+    }
+}
+
+// The name ciphertext is sent under, the real extension would only give away what kind of file it is
+fn encrypted_filename(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .with_extension("bin")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The file part of a multipart body, with the file's name and the type its extension implies
+fn file_part(
+    data: &[u8],
+    filename: &str,
+) -> Result<reqwest::blocking::multipart::Part, Box<dyn std::error::Error>> {
+    Ok(reqwest::blocking::multipart::Part::bytes(data.to_vec())
+        .file_name(filename.to_string())
+        .mime_str(file_mime_type(filename))?)
+}
+
+fn file_mime_type(filename: &str) -> &'static str {
+    let format = std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+
+    util::mime_type(format)
+}
+
+// Builds the request that would be sent to an HTTP uploader for the given data, without sending it.
+pub fn build_request(
+    client: &reqwest::blocking::Client,
+    u: &HttpUploader,
+    data: &[u8],
+    filename: &str,
+) -> Result<reqwest::blocking::RequestBuilder, Box<dyn std::error::Error>> {
+    let mut req = client.request(request_method(u), &u.request_url);
+
+    if let Some(ref parameters) = u.parameters {
+        req = req.query(parameters);
+    }
+
+    if let Some(ref headers) = u.headers {
+        for (k, v) in headers {
+            req = req.header(k, v);
+        }
+    }
+
+    let file_form_name = u.file_form_name.clone().unwrap_or("image".to_string());
+
+    match &u.body {
+        config::Body::MultipartFormData => {
+            let mut form = reqwest::blocking::multipart::Form::new()
+                .part(file_form_name, file_part(data, filename)?);
+
+            if let Some(ref arguments) = u.arguments {
+                for (k, v) in arguments {
+                    form = form.text(k.clone(), v.clone());
                 }
+            }
+
+            req = req.multipart(form);
+        }
+
+        config::Body::FormURLEncoded => {
+            let url_encoded = deserialize_to_x_www_form_urlencoded(data)?;
+
+            req = req
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(url_encoded);
+        }
+
+        config::Body::JSON => {
+            let json = serde_json::json!({
+                file_form_name: std::str::from_utf8(data)?
+            });
+
+            req = req.json(&json);
+        }
+
+        config::Body::XML => {
+            let xml = format!(
+                r#"<xml><name>{}</name><file>{}</file></xml>"#,
+                file_form_name,
+                std::str::from_utf8(data)?
+            );
 
-                results.push(UploadResult {
-                    uploader_name: u.name.clone(),
-                    url: Some(url),
-                    thumbnail_url: Some(thumbnail_url),
-                    deletion_url: Some(deletion_url),
-                    error_message: None,
-                    file_path: None,
-                });
+            req = req.header("Content-Type", "application/xml").body(xml);
+        }
+
+        config::Body::Binary => {
+            req = req
+                .header("Content-Type", "application/octet-stream")
+                .body(data.to_vec());
+        }
+
+        _ => {
+            req = req
+                .header("Content-Type", "application/octet-stream")
+                .body(data.to_vec());
+        }
+    };
+
+    Ok(req)
+}
+
+// Turns the body of a successful response into an UploadResult using the uploader's custom syntax fields.
+pub fn parse_response(
+    u: &HttpUploader,
+    text: &str,
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    if let Some(ref path) = u.response_script {
+        return script::parse_response(path, &u.name, text);
+    }
+
+    // a field that isn't configured, or that the response has nothing for, is left out rather than empty
+    let parse = |syntax: Option<&String>| -> Result<Option<String>, Box<dyn std::error::Error>> {
+        match syntax {
+            Some(syntax) if !syntax.is_empty() => {
+                Ok(non_empty(util::parse_custom_syntax(syntax, text)?))
             }
+            _ => Ok(None),
+        }
+    };
 
-            Uploader::File(ref u) => {
-                let filename = format!(
-                    "{}/{}.{}",
-                    u.file_path,
-                    conf.make_filename(Some(&u.file_name)),
-                    format
-                );
+    Ok(UploadResult {
+        url: parse(Some(&u.url))?,
+        thumbnail_url: parse(u.thumbnail_url.as_ref())?,
+        deletion_url: parse(u.deletion_url.as_ref())?,
+        album_item: parse(u.album.as_ref().map(|a| &a.item))?,
+        ..UploadResult::new(&u.name)
+    })
+}
+
+fn non_empty(s: String) -> Option<String> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+// Groups uploads into an album using the uploader's album config, items are the album_item of each upload
+pub fn create_album(
+    u: &HttpUploader,
+    items: &[String],
+) -> Result<UploadResult, Box<dyn std::error::Error>> {
+    let album = u.album.as_ref().ok_or("Uploader doesn't support albums")?;
+
+    let client = reqwest::blocking::Client::new();
+
+    let method = reqwest::Method::from_bytes(album.request_method.to_uppercase().as_bytes())?;
+    let mut req = client.request(method, &album.request_url);
+
+    if let Some(ref headers) = u.headers {
+        for (k, v) in headers {
+            req = req.header(k, v);
+        }
+    }
+
+    let form: Vec<(&str, &str)> = items
+        .iter()
+        .map(|item| (album.item_form_name.as_str(), item.as_str()))
+        .collect();
+
+    let started = Instant::now();
+    let (response, text) = ResponseInfo::read(req.form(&form).send()?)?;
+    let duration_ms = Some(started.elapsed().as_millis() as u64);
+
+    if !response.is_success() {
+        let error_message = format!("{}: {}", response.status, text);
+
+        return Ok(UploadResult {
+            duration_ms,
+            response: Some(response),
+            ..UploadResult::error(&u.name, error_message)
+        });
+    }
+
+    let deletion_url = match album.deletion_url {
+        Some(ref syntax) => non_empty(util::parse_custom_syntax(syntax, &text)?),
+        None => None,
+    };
+
+    Ok(UploadResult {
+        url: non_empty(util::parse_custom_syntax(&album.url, &text)?),
+        deletion_url,
+        duration_ms,
+        response: Some(response),
+        ..UploadResult::new(&u.name)
+    })
+}
+
+// A human readable description of the request an HTTP uploader would send, used for debugging uploaders.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestSummary {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<String>,
+    pub curl: String,
+}
+
+// Describes the request `upload` would send to the given uploader, without sending it.
+// file_path is used to build the curl equivalent, it should point to a file containing data, and its name is the
+// name the file is sent under. An encrypting uploader is described with freshly encrypted data, which is written
+// to a temporary file for curl. A webhook is described announcing webhook_test_source.
+pub fn dry_run(
+    u: &HttpUploader,
+    data: &[u8],
+    file_path: &str,
+) -> Result<RequestSummary, Box<dyn std::error::Error>> {
+    let webhook = matches!(u.destination_type, config::DestinationType::Webhook);
 
-                std::fs::create_dir_all(&u.file_path)?;
+    let mut filename = std::path::Path::new(file_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or(file_path.to_string());
 
-                std::fs::write(&filename, data)?;
+    let encrypted = if u.encrypt && !webhook {
+        filename = encrypted_filename(&filename);
 
-                results.push(UploadResult {
-                    uploader_name: u.name.clone(),
-                    url: None,
-                    thumbnail_url: None,
-                    deletion_url: None,
-                    error_message: None,
-                    file_path: Some(filename),
-                });
+        let encrypted = crypto::encrypt(data)?;
+        let path = util::write_temp_file(&encrypted.data, "bin")?;
+        Some((encrypted.data, path.to_string_lossy().into_owned()))
+    } else {
+        None
+    };
+
+    let (data, sent_path) = match encrypted {
+        Some((ref data, ref path)) => (data.as_slice(), path.as_str()),
+        None => (data, file_path),
+    };
+
+    let client = reqwest::blocking::Client::new();
+
+    // multipart bodies are streamed, so they're described from these parts instead, None being the file
+    let (request, parts) = if webhook {
+        let source = webhook_test_source(u, &filename);
+        let payload = webhook_payload(u, &source, &filename, &UploadOptions::default());
+
+        let parts = vec![
+            (u.file_form_name.clone().unwrap_or("file".to_string()), None),
+            ("payload_json".to_string(), Some(payload.clone())),
+        ];
+
+        (
+            build_webhook_request(&client, u, Some(data), &filename, payload)?,
+            parts,
+        )
+    } else {
+        let mut parts = vec![(
+            u.file_form_name.clone().unwrap_or("image".to_string()),
+            None,
+        )];
+        if let Some(ref arguments) = u.arguments {
+            parts.extend(arguments.iter().map(|(k, v)| (k.clone(), Some(v.clone()))));
+        }
+
+        (build_request(&client, u, data, &filename)?, parts)
+    };
+    let request = request.build()?;
+
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+
+    let headers: Vec<(String, String)> = request
+        .headers()
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+
+    let mut body = Vec::new();
+    let mut curl = format!("curl -X {} {}", method, shell_quote(&url));
+
+    if encrypted.is_some() {
+        body.push(format!(
+            "encrypted, the key would only be added to the returned link, ciphertext saved to {}",
+            sent_path
+        ));
+    }
+
+    let multipart = matches!(u.body, config::Body::MultipartFormData);
+
+    for (k, v) in &headers {
+        // curl generates its own boundary for multipart bodies, so the header we built can't be reused
+        if multipart && k.eq_ignore_ascii_case("content-type") {
+            continue;
+        }
+
+        curl.push_str(&format!(" -H {}", shell_quote(&format!("{}: {}", k, v))));
+    }
+
+    match request.body().and_then(|b| b.as_bytes()) {
+        None if multipart => {
+            body.push("multipart/form-data".to_string());
+
+            for (name, value) in &parts {
+                match value {
+                    None => {
+                        let mime_type = file_mime_type(&filename);
+
+                        body.push(format!(
+                            "{} file part: {} ({}), {} bytes",
+                            name,
+                            filename,
+                            mime_type,
+                            data.len()
+                        ));
+                        curl.push_str(&format!(
+                            " -F {}",
+                            shell_quote(&format!(
+                                "{}=@{};filename={};type={}",
+                                name, sent_path, filename, mime_type
+                            ))
+                        ));
+                    }
+                    Some(value) => {
+                        body.push(format!("{} text part: {}", name, truncate(value, 512)));
+                        // --form-string, since -F would read a value starting with @ or < from a file
+                        curl.push_str(&format!(
+                            " --form-string {}",
+                            shell_quote(&format!("{}={}", name, value))
+                        ));
+                    }
+                }
+            }
+        }
+        None => body.push("empty".to_string()),
+        Some(bytes) => {
+            body.push(format!("{} bytes", bytes.len()));
+
+            match std::str::from_utf8(bytes) {
+                Ok(text) => {
+                    body.push(truncate(text, 512));
+                    curl.push_str(&format!(" --data-raw {}", shell_quote(text)));
+                }
+                Err(_) => {
+                    curl.push_str(&format!(
+                        " --data-binary {}",
+                        shell_quote(&format!("@{}", sent_path))
+                    ));
+                }
             }
         }
     }
 
-    Ok(results)
+    Ok(RequestSummary {
+        method,
+        url,
+        headers,
+        body,
+        curl,
+    })
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    match s.char_indices().nth(max) {
+        Some((i, _)) => format!("{}...", &s[..i]),
+        None => s.to_string(),
+    }
 }
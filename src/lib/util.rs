@@ -81,6 +81,124 @@ fn generate_random_string(l: usize) -> String {
     iter::repeat_with(one_char).take(l).collect()
 }
 
+// Writes data to a uniquely named file in the temp directory, the caller is responsible for removing it
+pub fn write_temp_file(
+    data: &[u8],
+    format: &str,
+) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    let path =
+        std::env::temp_dir().join(format!("delenix-{}.{}", generate_random_string(12), format));
+
+    std::fs::write(&path, data)?;
+
+    Ok(path)
+}
+
+// Writes the file under a temporary name next to it and renames it into place, so a process reading it at the
+// same time sees either the old or the new contents, never half of the new ones. The file is only readable by
+// the user, what's kept this way holds links and deletion URLs.
+pub fn write_atomic(path: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = format!("{}.{}.tmp", path, generate_random_string(8));
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    if let Err(e) = options
+        .open(&tmp)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, data))
+    {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+// Replaces every $name$ in the template with its value, unknown names are left as they are
+pub fn fill_template(template: &str, vars: &[(&str, String)]) -> String {
+    let mut result = template.to_string();
+
+    for (name, value) in vars {
+        result = result.replace(&format!("${}$", name), value);
+    }
+
+    result
+}
+
+// Parses durations like 90, 30s, 15m, 24h, 7d or 2w into seconds, a bare number is in seconds
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {}, expected e.g. 30m, 24h or 7d", s))?;
+
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Unknown unit {} in duration {}, expected s, m, h, d or w",
+                unit, s
+            ))
+        }
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Duration {} is too long", s))
+}
+
+// Extensions and the MIME types they're served as, the first extension of a type is the one it's saved as
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("pdf", "application/pdf"),
+];
+
+// The MIME type for a file extension, falling back to a generic binary type
+pub fn mime_type(format: &str) -> &'static str {
+    let format = format.to_lowercase();
+
+    MIME_TYPES
+        .iter()
+        .find(|(ext, _)| *ext == format)
+        .map(|(_, mime)| *mime)
+        .unwrap_or("application/octet-stream")
+}
+
+// The extension for a MIME type, parameters like charset are ignored
+pub fn extension(mime_type: &str) -> Option<&'static str> {
+    let mime_type = mime_type.split(';').next()?.trim().to_lowercase();
+
+    MIME_TYPES
+        .iter()
+        .find(|(_, mime)| *mime == mime_type)
+        .map(|(ext, _)| *ext)
+}
+
 pub fn make_default_config_path() -> String {
     home::home_dir()
         .unwrap()
@@ -90,6 +208,33 @@ pub fn make_default_config_path() -> String {
         .to_string()
 }
 
+pub fn make_default_cache_path() -> String {
+    home::home_dir()
+        .unwrap()
+        .join(".cache/delenix/uploads.json")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+pub fn make_default_expiry_path() -> String {
+    home::home_dir()
+        .unwrap()
+        .join(".local/share/delenix/expiry.json")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+pub fn make_default_uploads_path() -> String {
+    home::home_dir()
+        .unwrap()
+        .join(".local/share/delenix/uploads")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
 pub fn make_default_image_path() -> String {
     home::home_dir()
         .unwrap()
@@ -99,40 +244,110 @@ pub fn make_default_image_path() -> String {
         .to_string()
 }
 
-pub fn handle_simple_upload(config: &config::Config, data: &[u8]) {
+// Uploads the data and logs the results, copying the URL to the clipboard if configured.
+// The results are returned so the caller can report them, errors are logged before being returned.
+pub fn handle_simple_upload(
+    config: &config::Config,
+    data: &[u8],
+    opts: &upload::UploadOptions,
+) -> Result<Vec<upload::UploadResult>, Box<dyn std::error::Error>> {
     tracing::info!("Uploading file");
-    match upload::upload(config, data, "png") {
-        Ok(results) => {
-            for result in results {
-                if result.error_message.is_some() {
-                    tracing::error!("Failed to upload: {}", result.error_message.unwrap());
-                    continue;
-                }
+    let results = match upload::upload(config, data, "png", opts) {
+        Ok(results) => results,
+        Err(e) => {
+            tracing::error!("Failed to upload: {}", e);
+            return Err(e);
+        }
+    };
 
-                if result.url.is_some() {
-                    let result_url = result.url.unwrap();
-                    tracing::info!("Uploaded URL: {}", result_url);
+    for result in &results {
+        if let Some(ref error_message) = result.error_message {
+            tracing::error!("Failed to upload: {}", error_message);
+            continue;
+        }
 
-                    if config.copy_url_to_clipboard {
-                        if let Err(e) = clipboard::copy_text_to_clipboard(&result_url) {
-                            tracing::error!("Failed to copy URL to clipboard: {}", e);
-                        }
-                    }
-                }
+        if let Some(ref result_url) = result.url {
+            tracing::info!("Uploaded URL: {}", result_url);
+        }
 
-                if result.deletion_url.is_some() {
-                    tracing::info!("Delete URL: {}", result.deletion_url.unwrap());
-                }
+        if let Some(ref deletion_url) = result.deletion_url {
+            tracing::info!("Delete URL: {}", deletion_url);
+        }
 
-                if result.file_path.is_some() {
-                    tracing::info!("File path: {}", result.file_path.unwrap());
-                }
-            }
+        if let Some(ref file_path) = result.file_path {
+            tracing::info!("File path: {}", file_path);
         }
-        Err(e) => {
-            tracing::error!("Failed to upload: {}", e);
+    }
+
+    if config.copy_url_to_clipboard {
+        if let Some(result) = clipboard_result(config, &results) {
+            let text = clipboard_text(config, result, opts.filename.as_deref());
+
+            if let Err(e) = clipboard::copy_text_to_clipboard(&text) {
+                tracing::error!("Failed to copy URL to clipboard: {}", e);
+            }
         }
     }
+
+    Ok(results)
+}
+
+// The result whose link goes on the clipboard, the primary uploader's if it succeeded, the last one otherwise
+fn clipboard_result<'a>(
+    config: &config::Config,
+    results: &'a [upload::UploadResult],
+) -> Option<&'a upload::UploadResult> {
+    let mut uploaded = results
+        .iter()
+        .filter(|r| r.error_message.is_none() && r.url.as_deref().is_some_and(|u| !u.is_empty()));
+
+    config
+        .primary_uploader
+        .as_ref()
+        .and_then(|primary| {
+            uploaded
+                .clone()
+                .find(|r| r.uploader_name.eq_ignore_ascii_case(primary))
+        })
+        .or_else(|| uploaded.next_back())
+}
+
+// Formats a result for the clipboard with the uploader's clipboard_template, or the global one, e.g.
+// ![$filename$]($url$) for Markdown or [img]$url$[/img] for BBCode. Defaults to the bare URL.
+pub fn clipboard_text(
+    config: &config::Config,
+    result: &upload::UploadResult,
+    filename: Option<&str>,
+) -> String {
+    let template = config
+        .uploader(&result.uploader_name)
+        .and_then(|u| u.clipboard_template())
+        .or(config.clipboard_template.as_deref())
+        .unwrap_or("$url$");
+
+    let url = result.url.clone().unwrap_or_default();
+
+    // fall back to the last part of the URL, which is usually the name the host gave the file
+    let filename = filename
+        .map(str::to_string)
+        .or_else(|| url.rsplit('/').next().map(str::to_string))
+        .unwrap_or_default();
+
+    let vars = [
+        ("url", url.clone()),
+        (
+            "thumbnail_url",
+            result.thumbnail_url.clone().unwrap_or_default(),
+        ),
+        (
+            "deletion_url",
+            result.deletion_url.clone().unwrap_or_default(),
+        ),
+        ("filename", filename),
+        ("uploader", result.uploader_name.clone()),
+    ];
+
+    fill_template(template, &vars)
 }
 
 lazy_static! {
@@ -226,4 +441,14 @@ macro_rules! handle_error {
             }
         }
     };
+    // returns $ret on error instead, e.g. an exit code
+    ($e:expr, $ret:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Error: {}", e);
+                return $ret;
+            }
+        }
+    };
 }
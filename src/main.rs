@@ -1,9 +1,11 @@
 use std::{thread, time};
 
-use delenix_lib::{clipboard, config, handle_error, ocr, screenshot, util};
+use delenix_lib::{batch, clipboard, config, crypto, handle_error, ocr, screenshot, upload, util};
 use structopt::StructOpt;
 
 mod ipc;
+mod output;
+mod serve;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "delenix", about = "A screenshotting and file uploading tool.")]
@@ -26,6 +28,40 @@ struct Cli {
     )]
     screenshot: bool,
 
+    #[structopt(
+        long = "monitor",
+        value_name = "MONITOR",
+        help = "Capture a whole monitor instead of selecting a region, by index, name (e.g. DP-1) or \"current\" for the one under the pointer"
+    )]
+    monitor: Option<screenshot::MonitorSelector>,
+
+    #[structopt(
+        long = "full",
+        conflicts_with = "monitor",
+        help = "Capture the whole screen instead of selecting a region"
+    )]
+    full: bool,
+
+    #[structopt(
+        long = "cursor",
+        help = "Include the mouse cursor in the capture, overrides the config"
+    )]
+    cursor: bool,
+
+    #[structopt(
+        long = "display",
+        value_name = "DISPLAY",
+        help = "The X display to capture, e.g. :99, overrides the config and $DISPLAY"
+    )]
+    display: Option<String>,
+
+    #[structopt(
+        long = "screen",
+        value_name = "N",
+        help = "The screen of the display to capture, overrides the config"
+    )]
+    screen: Option<usize>,
+
     #[structopt(
         short = "t",
         long = "tesseract",
@@ -47,15 +83,208 @@ struct Cli {
         help = "File to upload, to be used with -u/--upload"
     )]
     file: Option<String>,
+
+    #[structopt(
+        long = "uploader",
+        value_name = "NAME",
+        number_of_values = 1,
+        help = "Only upload to the uploader with this name, may be repeated"
+    )]
+    uploaders: Vec<String>,
+
+    #[structopt(
+        long = "no-upload",
+        conflicts_with_all = &["uploaders", "only_save"],
+        help = "Don't send the screenshot to any uploader"
+    )]
+    no_upload: bool,
+
+    #[structopt(
+        long = "only-save",
+        conflicts_with = "uploaders",
+        help = "Only send the screenshot to the file uploaders, i.e. save it locally"
+    )]
+    only_save: bool,
+
+    #[structopt(
+        long = "force",
+        help = "Upload even if the file was already uploaded to the same uploader"
+    )]
+    force: bool,
+
+    #[structopt(
+        long = "expire",
+        value_name = "DURATION",
+        parse(try_from_str = util::parse_duration),
+        help = "Delete the uploads after this long, e.g. 30m, 24h or 7d, requires the daemon to be running"
+    )]
+    expire: Option<u64>,
+
+    #[structopt(
+        long = "output",
+        value_name = "FORMAT",
+        possible_values = &["json", "plain", "url"],
+        help = "Print the results to stdout as json, plain or url, logs always go to stderr"
+    )]
+    output: Option<output::Format>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    fn upload_options(&self) -> upload::UploadOptions {
+        let selection = if self.no_upload {
+            upload::Selection::None
+        } else if self.only_save {
+            upload::Selection::OnlySave
+        } else if !self.uploaders.is_empty() {
+            upload::Selection::Named(self.uploaders.clone())
+        } else {
+            upload::Selection::All
+        };
+
+        upload::UploadOptions {
+            selection,
+            force: self.force,
+            expire: self.expire,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(about = "Inspect and debug the configured uploaders")]
+    Uploader(UploaderCommand),
+
+    #[structopt(
+        about = "Upload several files at once, grouping them into an album where supported"
+    )]
+    Upload {
+        #[structopt(
+            required = true,
+            help = "Files to upload, directories and glob patterns are expanded"
+        )]
+        files: Vec<String>,
+    },
+
+    #[structopt(
+        about = "Capture a window, then copy and upload it like a screenshot",
+        group = structopt::clap::ArgGroup::with_name("target").required(true).multiple(true),
+        group = structopt::clap::ArgGroup::with_name("match").multiple(true)
+    )]
+    Capture {
+        #[structopt(
+            long = "window",
+            group = "target",
+            conflicts_with_all = &["active", "match"],
+            help = "Pick the window to capture by clicking on it"
+        )]
+        window: bool,
+
+        #[structopt(
+            long = "active",
+            group = "target",
+            conflicts_with = "match",
+            help = "Capture the focused window"
+        )]
+        active: bool,
+
+        #[structopt(
+            long = "window-class",
+            value_name = "CLASS",
+            groups = &["target", "match"],
+            help = "Capture the window with this class or instance name, e.g. firefox"
+        )]
+        window_class: Option<String>,
+
+        #[structopt(
+            long = "window-title",
+            value_name = "REGEX",
+            parse(try_from_str = regex::Regex::new),
+            groups = &["target", "match"],
+            help = "Capture the window whose title matches this regular expression"
+        )]
+        window_title: Option<regex::Regex>,
+
+        #[structopt(
+            long = "topmost",
+            requires = "match",
+            help = "Capture the topmost window when several match, instead of failing"
+        )]
+        topmost: bool,
+
+        #[structopt(
+            long = "no-frame",
+            help = "Leave out the window manager's decorations, overrides the config"
+        )]
+        no_frame: bool,
+    },
+
+    #[structopt(about = "List the windows the window manager manages, bottom to top")]
+    Windows {
+        #[structopt(long = "json", help = "Print the windows as JSON")]
+        json: bool,
+    },
+
+    #[structopt(about = "Run an upload server that delenix and ShareX can upload to")]
+    Serve(serve::Options),
+
+    #[structopt(about = "Download and decrypt an encrypted upload")]
+    Fetch {
+        #[structopt(help = "Link returned for the upload, including the key after the #")]
+        url: String,
+
+        #[structopt(
+            short = "o",
+            long = "out",
+            value_name = "FILE",
+            help = "Write the file here instead of to stdout"
+        )]
+        out: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum UploaderCommand {
+    #[structopt(about = "Show the request an uploader would send, and optionally send it")]
+    Test {
+        #[structopt(help = "Name of the uploader to test")]
+        name: String,
+
+        #[structopt(long = "dry-run", help = "Only print the request, don't send it")]
+        dry_run: bool,
+
+        #[structopt(
+            long = "response",
+            value_name = "FILE",
+            help = "Parse a canned response body instead of sending the request"
+        )]
+        response: Option<String>,
+
+        #[structopt(
+            short = "f",
+            long = "file",
+            value_name = "FILE",
+            help = "File to send, a placeholder image is used if not specified"
+        )]
+        file: Option<String>,
+    },
 }
 
 fn main() {
-    tracing_subscriber::fmt::init();
+    // logs go to stderr so stdout is left for --output
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     let opt = Cli::from_args();
+    let upload_options = opt.upload_options();
 
     // if no arguments are specified, show help
-    if !opt.daemon && !opt.screenshot && !opt.upload && opt.file.is_none() {
+    if !opt.daemon && !opt.screenshot && !opt.upload && opt.file.is_none() && opt.command.is_none()
+    {
         Cli::clap().print_help().expect("Failed to print help");
         return;
     }
@@ -66,12 +295,90 @@ fn main() {
         return;
     }
 
-    let config_path = opt.config.unwrap_or_else(util::make_default_config_path);
-    let config = {
-        tracing::info!("Loading config from {}", config_path);
-        config::Config::from_file(config_path).unwrap()
+    let config_path = opt
+        .config
+        .clone()
+        .unwrap_or_else(util::make_default_config_path);
+    tracing::info!("Loading config from {}", config_path);
+    let mut config = match config::Config::from_file(config_path.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Failed to load the config from {}: {}", config_path, e);
+            std::process::exit(output::EXIT_FAILURE);
+        }
     };
 
+    if opt.display.is_some() {
+        config.display = opt.display.clone();
+    }
+    if opt.screen.is_some() {
+        config.screen = opt.screen;
+    }
+    if opt.cursor {
+        config.show_cursor = true;
+    }
+    if let Some(Command::Capture { no_frame: true, .. }) = opt.command {
+        config.exclude_window_frame = true;
+    }
+
+    // checked up front, so a typo doesn't cost the user a capture
+    if let Err(e) = upload_options.selection.check(&config) {
+        tracing::error!("{}", e);
+        std::process::exit(output::EXIT_FAILURE);
+    }
+
+    let mut report = output::Report::default();
+
+    if let Some(ref command) = opt.command {
+        let code = match command {
+            Command::Uploader(UploaderCommand::Test {
+                name,
+                dry_run,
+                response,
+                file,
+            }) => test_uploader(
+                &config,
+                name,
+                *dry_run,
+                response.clone(),
+                file.clone(),
+                opt.output.is_some(),
+                &mut report,
+            ),
+            Command::Upload { files } => upload_files(&config, files, &upload_options, &mut report),
+            Command::Capture {
+                window,
+                active,
+                window_class,
+                window_title,
+                topmost,
+                ..
+            } => {
+                let target = if *window {
+                    WindowTarget::Pick
+                } else if *active {
+                    WindowTarget::Active
+                } else {
+                    WindowTarget::Match(screenshot::WindowMatch {
+                        class: window_class.clone(),
+                        title: window_title.clone(),
+                        topmost: *topmost,
+                    })
+                };
+                capture_window(&config, &opt, target, &upload_options, &mut report)
+            }
+            Command::Windows { json } => list_windows(&config, *json),
+            Command::Serve(serve_options) => serve::run(&config, serve_options),
+            Command::Fetch { url, out } => fetch(url, out.as_deref()),
+        };
+
+        if let Some(format) = opt.output {
+            report.print(format);
+        }
+
+        std::process::exit(code);
+    }
+
     if opt.daemon {
         tracing::info!("Starting daemon");
 
@@ -84,43 +391,514 @@ fn main() {
         return;
     }
 
-    if opt.upload {
-        tracing::info!("Uploading file");
-        if let Some(ref path) = opt.file {
-            let data = handle_error!(std::fs::read(&path));
-            util::handle_simple_upload(&config, &data);
-        } else {
+    let code = if opt.upload {
+        upload_file(&config, &opt, &upload_options, &mut report)
+    } else if opt.file.is_some() && opt.tesseract {
+        recognise_file(&config, &opt, &mut report)
+    } else if opt.screenshot {
+        take_screenshot(&config, &opt, &upload_options, &mut report)
+    } else {
+        0
+    };
+
+    if let Some(format) = opt.output {
+        report.print(format);
+    }
+
+    std::process::exit(code);
+}
+
+// The functions below return the exit code of the invocation, filling in the report as they go
+
+fn upload_file(
+    config: &config::Config,
+    opt: &Cli,
+    upload_options: &upload::UploadOptions,
+    report: &mut output::Report,
+) -> i32 {
+    tracing::info!("Uploading file");
+    let path = match opt.file {
+        Some(ref path) => path,
+        None => {
             tracing::error!("No file specified to upload");
+            return output::EXIT_FAILURE;
         }
+    };
 
-        return;
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::error!("Failed to read {}: {}", path, e);
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    match util::handle_simple_upload(config, &data, upload_options) {
+        Ok(results) => report.results = results,
+        Err(_) => return output::EXIT_UPLOAD_FAILED,
     }
 
-    if opt.file.is_some() && opt.tesseract {
-        tracing::info!("Getting text from file");
-        let data = handle_error!(std::fs::read(opt.file.unwrap()));
+    if report.upload_failed() {
+        return output::EXIT_UPLOAD_FAILED;
+    }
 
-        let text = handle_error!(ocr::ocr(&config.tessdata_path, &data));
-        handle_error!(clipboard::copy_text_to_clipboard(&text));
-        return;
+    0
+}
+
+fn upload_files(
+    config: &config::Config,
+    files: &[String],
+    upload_options: &upload::UploadOptions,
+    report: &mut output::Report,
+) -> i32 {
+    let paths = match batch::expand_paths(files) {
+        Ok(paths) => paths,
+        Err(e) => {
+            tracing::error!("Failed to expand paths: {}", e);
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    if paths.is_empty() {
+        tracing::error!("No files to upload");
+        return output::EXIT_FAILURE;
+    }
+
+    tracing::info!("Uploading {} files", paths.len());
+    let result = batch::upload(config, &paths, upload_options);
+
+    for album in &result.albums {
+        match (&album.url, &album.error_message) {
+            (_, Some(error)) => tracing::error!(
+                "Failed to create album on {}: {}",
+                album.uploader_name,
+                error
+            ),
+            (Some(url), None) => tracing::info!("Album URL: {}", url),
+            _ => {}
+        }
+    }
+
+    for file in &result.files {
+        if let Some(ref error) = file.error_message {
+            tracing::error!("Failed to upload {}: {}", file.path, error);
+        }
+
+        for r in &file.results {
+            match (&r.url, &r.file_path, &r.error_message) {
+                (_, _, Some(error)) => tracing::error!("Failed to upload {}: {}", file.path, error),
+                (Some(url), _, None) => tracing::info!("{}: {}", file.path, url),
+                (None, Some(path), None) => tracing::info!("{}: saved to {}", file.path, path),
+                _ => {}
+            }
+        }
+    }
+
+    for webhook in &result.webhooks {
+        if let Some(ref error) = webhook.error_message {
+            tracing::error!("Failed to notify {}: {}", webhook.uploader_name, error);
+        }
+    }
+
+    let failed = result.failed();
+    report.albums = result.albums;
+    report.files = result.files;
+    report.results = result.webhooks;
+
+    if failed {
+        return output::EXIT_UPLOAD_FAILED;
+    }
+
+    0
+}
+
+fn recognise_file(config: &config::Config, opt: &Cli, report: &mut output::Report) -> i32 {
+    tracing::info!("Getting text from file");
+    let path = opt.file.as_deref().unwrap_or_default();
+
+    let text = match std::fs::read(path)
+        .map_err(|e| e.into())
+        .and_then(|data| ocr::ocr(&config.tessdata_path, &data))
+    {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::error!("Failed to get text from {}: {}", path, e);
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    if let Err(e) = clipboard::copy_text_to_clipboard(&text) {
+        tracing::error!("Failed to copy text to clipboard: {}", e);
     }
 
-    if opt.screenshot {
-        tracing::info!("Taking screenshot");
-        let rs = screenshot::select_region(config.freeze_screen).unwrap();
-        thread::sleep(time::Duration::from_millis(30)); // this is a hack to fix the screenshot sometimes displaying the dim and selection rectangle
-        let png = handle_error!(config.screenshot(screenshot::ScreenshotType::Region(rs)));
+    report.text = Some(text);
+
+    0
+}
+
+fn take_screenshot(
+    config: &config::Config,
+    opt: &Cli,
+    upload_options: &upload::UploadOptions,
+    report: &mut output::Report,
+) -> i32 {
+    tracing::info!("Taking screenshot");
+    let session = match config.session() {
+        Ok(session) => session,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return output::EXIT_CAPTURE_FAILED;
+        }
+    };
+
+    let rs = match opt.monitor {
+        _ if opt.full => match session.screen_region() {
+            Ok(rs) => rs,
+            Err(e) => {
+                tracing::error!("Failed to get the screen's size: {}", e);
+                return output::EXIT_CAPTURE_FAILED;
+            }
+        },
+        Some(ref selector) => match session.find_monitor(selector) {
+            Ok(monitor) => (&monitor).into(),
+            Err(e) => {
+                tracing::error!("Failed to find monitor: {}", e);
+                return output::EXIT_CAPTURE_FAILED;
+            }
+        },
+        None => {
+            let rs = match session
+                .select_region(config.freeze_screen, config.selection_monitor.as_ref())
+            {
+                Ok(rs) => rs,
+                Err(e) if e.is::<screenshot::Cancelled>() => {
+                    tracing::info!("{}", e);
+                    return output::EXIT_CANCELLED;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to select region: {}", e);
+                    return output::EXIT_CAPTURE_FAILED;
+                }
+            };
+            thread::sleep(time::Duration::from_millis(30)); // this is a hack to fix the screenshot sometimes displaying the dim and selection rectangle
+
+            rs
+        }
+    };
+
+    report.geometry = Some((&rs).into());
 
-        if opt.tesseract {
-            println!("{}", ocr::ocr(&config.tessdata_path, &png).unwrap());
+    let png = match config.screenshot_with(&session, screenshot::ScreenshotType::Region(rs)) {
+        Ok(png) => png,
+        Err(e) => {
+            tracing::error!("Failed to take screenshot: {}", e);
+            return output::EXIT_CAPTURE_FAILED;
         }
+    };
+
+    handle_capture(config, opt, &png, upload_options, report)
+}
 
-        if config.copy_to_clipboard {
-            handle_error!(clipboard::copy_png_to_clipboard(&png));
+enum WindowTarget {
+    Pick,   // clicked on
+    Active, // focused
+    Match(screenshot::WindowMatch),
+}
+
+fn capture_window(
+    config: &config::Config,
+    opt: &Cli,
+    target: WindowTarget,
+    upload_options: &upload::UploadOptions,
+    report: &mut output::Report,
+) -> i32 {
+    tracing::info!("Capturing window");
+    let session = match config.session() {
+        Ok(session) => session,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return output::EXIT_CAPTURE_FAILED;
         }
+    };
+
+    // the client windows are captured, their frame is added back unless the config leaves it out
+    let window = match target {
+        // same hack as for regions, let the overlay disappear first
+        WindowTarget::Pick => session.pick_window().and_then(|window| {
+            thread::sleep(time::Duration::from_millis(30));
+            Ok(session.client_window(window)?)
+        }),
+        WindowTarget::Active => match session.get_active_window_id() {
+            Ok(Some(window)) => session.client_window(window).map_err(Into::into),
+            Ok(None) => Err("No window has the focus".into()),
+            Err(e) => Err(e.into()),
+        },
+        WindowTarget::Match(ref window_match) => session
+            .list_windows()
+            .and_then(|windows| Ok(window_match.find(&windows)?.id)),
+    };
+
+    let window = match window {
+        Ok(window) => window,
+        Err(e) if e.is::<screenshot::Cancelled>() => {
+            tracing::info!("{}", e);
+            return output::EXIT_CANCELLED;
+        }
+        Err(e) => {
+            tracing::error!("Failed to find the window: {}", e);
+            return output::EXIT_CAPTURE_FAILED;
+        }
+    };
+
+    if let Ok(rs) = session.window_region(window, !config.exclude_window_frame) {
+        report.geometry = Some((&rs).into());
+    }
+
+    let png = match config.screenshot_with(&session, screenshot::ScreenshotType::Window(window)) {
+        Ok(png) => png,
+        Err(e) => {
+            tracing::error!("Failed to take screenshot: {}", e);
+            return output::EXIT_CAPTURE_FAILED;
+        }
+    };
+
+    handle_capture(config, opt, &png, upload_options, report)
+}
+
+// What's done with every capture: recognising text, copying and uploading it
+fn handle_capture(
+    config: &config::Config,
+    opt: &Cli,
+    png: &[u8],
+    upload_options: &upload::UploadOptions,
+    report: &mut output::Report,
+) -> i32 {
+    if opt.tesseract {
+        match ocr::ocr(&config.tessdata_path, png) {
+            // keep the old behaviour of printing the text when the report isn't going to
+            Ok(text) if opt.output.is_none() => println!("{}", text),
+            Ok(text) => report.text = Some(text),
+            Err(e) => tracing::error!("Failed to get text from screenshot: {}", e),
+        }
+    }
+
+    if config.copy_to_clipboard {
+        if let Err(e) = clipboard::copy_png_to_clipboard(png) {
+            tracing::error!("Failed to copy screenshot to clipboard: {}", e);
+        }
+    }
+
+    if !config.uploaders.is_empty() {
+        match util::handle_simple_upload(config, png, upload_options) {
+            Ok(results) => report.results = results,
+            Err(_) => return output::EXIT_UPLOAD_FAILED,
+        }
+    }
+
+    if report.upload_failed() {
+        return output::EXIT_UPLOAD_FAILED;
+    }
+
+    0
+}
+
+fn test_uploader(
+    config: &config::Config,
+    name: &str,
+    dry_run: bool,
+    response: Option<String>,
+    file: Option<String>,
+    to_stderr: bool,
+    report: &mut output::Report,
+) -> i32 {
+    // with --output the report is what goes to stdout, so the description goes to stderr instead
+    let print = |line: &str| {
+        if to_stderr {
+            eprintln!("{}", line)
+        } else {
+            println!("{}", line)
+        }
+    };
+
+    let uploader = match config.uploader(name) {
+        Some(uploader) => uploader,
+        None => {
+            tracing::error!("No uploader named {} in the config", name);
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    let u = match uploader {
+        config::Uploader::HTTP(u) => u,
+        _ => {
+            tracing::error!("Only HTTP uploaders can be tested");
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    let (data, file_path) = match file {
+        Some(path) => (
+            handle_error!(std::fs::read(&path), output::EXIT_FAILURE),
+            path,
+        ),
+        // a single white pixel, enough for most hosts to accept it as an image
+        None => (
+            handle_error!(screenshot::as_png(vec![255; 4], 1, 1), output::EXIT_FAILURE),
+            "screenshot.png".to_string(),
+        ),
+    };
+
+    let summary = handle_error!(upload::dry_run(u, &data, &file_path), output::EXIT_FAILURE);
+
+    print(&format!("Request:\n  {} {}", summary.method, summary.url));
+    print("Headers:");
+    for (k, v) in &summary.headers {
+        print(&format!("  {}: {}", k, v));
+    }
+    print("Body:");
+    for line in &summary.body {
+        print(&format!("  {}", line));
+    }
+    print(&format!("curl:\n  {}", summary.curl));
+
+    if let Some(path) = response {
+        let text = handle_error!(std::fs::read_to_string(&path), output::EXIT_FAILURE);
+        let result = handle_error!(upload::parse_response(u, &text), output::EXIT_FAILURE);
+
+        print("Parsed response:");
+        print_upload_result(&print, &result);
+        report.results.push(result);
+        return 0;
+    }
+
+    if dry_run {
+        return 0;
+    }
+
+    // only the uploader itself runs, a test shouldn't fire hooks, fill the cache or schedule a deletion
+    let filename = std::path::Path::new(&file_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or(file_path.clone());
+    let result = if uploader.is_webhook() {
+        let source = upload::webhook_test_source(u, &filename);
+        let client = reqwest::blocking::Client::new();
+        let opts = Default::default();
+        upload::notify_webhook(&client, u, Some(&data), &source, &filename, &opts)
+    } else {
+        let opts = Default::default();
+        upload::upload_to(config, &mut None, uploader, &data, "png", &filename, &opts)
+    };
+    let result = handle_error!(result, output::EXIT_UPLOAD_FAILED);
+
+    print("Response:");
+    print_upload_result(&print, &result);
+
+    let failed = result.error_message.is_some();
+    report.results.push(result);
+
+    if failed {
+        return output::EXIT_UPLOAD_FAILED;
+    }
+
+    0
+}
+
+fn list_windows(config: &config::Config, json: bool) -> i32 {
+    let windows = match config.session().and_then(|session| session.list_windows()) {
+        Ok(windows) => windows,
+        Err(e) => {
+            tracing::error!("Failed to list windows: {}", e);
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    if json {
+        match serde_json::to_string_pretty(&windows) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                tracing::error!("Failed to serialize windows: {}", e);
+                return output::EXIT_FAILURE;
+            }
+        }
+
+        return 0;
+    }
+
+    for window in windows {
+        println!(
+            "{:#010x}  {}x{}+{}+{}  {}  {}{}",
+            window.id,
+            window.width,
+            window.height,
+            window.x,
+            window.y,
+            window.class.as_deref().unwrap_or("-"),
+            window.title.as_deref().unwrap_or(""),
+            if window.visible { "" } else { " (hidden)" }
+        );
+    }
+
+    0
+}
+
+fn fetch(url: &str, out: Option<&str>) -> i32 {
+    let data = match crypto::fetch(url) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::error!("Failed to fetch {}: {}", url, e);
+            return output::EXIT_FAILURE;
+        }
+    };
+
+    let written = match out {
+        Some(path) => std::fs::write(path, &data),
+        None => std::io::Write::write_all(&mut std::io::stdout(), &data),
+    };
+
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            tracing::error!("Failed to write the file: {}", e);
+            output::EXIT_FAILURE
+        }
+    }
+}
+
+fn print_upload_result(print: &dyn Fn(&str), result: &upload::UploadResult) {
+    let fields = [
+        ("url", &result.url),
+        ("thumbnail_url", &result.thumbnail_url),
+        ("deletion_url", &result.deletion_url),
+        ("error_message", &result.error_message),
+    ];
+
+    for (name, value) in fields {
+        print(&format!(
+            "  {}: {}",
+            name,
+            value.as_deref().unwrap_or("(none)")
+        ));
+    }
+
+    if let Some(duration_ms) = result.duration_ms {
+        print(&format!("  duration: {}ms", duration_ms));
+    }
 
-        if !config.uploaders.is_empty() {
-            util::handle_simple_upload(&config, &png);
+    if let Some(ref response) = result.response {
+        print(&format!("  status: {}", response.status));
+        print("  headers:");
+        for (k, v) in &response.headers {
+            print(&format!("    {}: {}", k, v));
         }
+        print(&format!(
+            "  body{}:\n{}",
+            if response.truncated {
+                " (truncated)"
+            } else {
+                ""
+            },
+            response.body
+        ));
     }
 }
@@ -0,0 +1,158 @@
+use std::str::FromStr;
+
+use delenix_lib::{batch::FileResult, screenshot::RegionSelection, upload::UploadResult};
+use serde_derive::Serialize;
+
+// Exit codes, so scripts can tell why an invocation failed without parsing the logs
+pub const EXIT_FAILURE: i32 = 1; // Any other error, e.g. a bad config or a missing file
+pub const EXIT_CANCELLED: i32 = 2; // The user cancelled the selection
+pub const EXIT_CAPTURE_FAILED: i32 = 3; // The screenshot couldn't be taken
+pub const EXIT_UPLOAD_FAILED: i32 = 4; // At least one uploader failed, the others may have succeeded
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Json,  // The whole report as a single JSON object
+    Plain, // One "key: value" line per field
+    Url,   // Only the uploaded URLs (or saved file paths), one per line
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "plain" => Ok(Self::Plain),
+            "url" => Ok(Self::Url),
+            _ => Err(format!(
+                "Unknown output format {}, expected json, plain or url",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Geometry {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl From<&RegionSelection> for Geometry {
+    fn from(selection: &RegionSelection) -> Self {
+        Self {
+            x: selection.x,
+            y: selection.y,
+            width: selection.w,
+            height: selection.h,
+        }
+    }
+}
+
+// Everything an invocation produced, printed to stdout at the end when --output is given
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Report {
+    pub geometry: Option<Geometry>,
+    pub text: Option<String>,
+    pub results: Vec<UploadResult>,
+    pub albums: Vec<UploadResult>, // Only set for batch uploads
+    pub files: Vec<FileResult>,    // Only set for batch uploads
+}
+
+impl Report {
+    pub fn upload_failed(&self) -> bool {
+        let failed = |r: &UploadResult| r.error_message.is_some();
+
+        self.results.iter().any(failed)
+            || self.albums.iter().any(failed)
+            || self
+                .files
+                .iter()
+                .any(|f| f.error_message.is_some() || f.results.iter().any(failed))
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Json => match serde_json::to_string(self) {
+                Ok(json) => println!("{}", json),
+                Err(e) => tracing::error!("Failed to serialize output: {}", e),
+            },
+            Format::Plain => self.print_plain(),
+            Format::Url => {
+                // albums first, so the single shareable link is always the first line
+                let results = self
+                    .albums
+                    .iter()
+                    .chain(&self.results)
+                    .chain(self.files.iter().flat_map(|f| &f.results));
+
+                for result in results {
+                    if let Some(location) = result.url.as_ref().or(result.file_path.as_ref()) {
+                        println!("{}", location);
+                    }
+                }
+            }
+        }
+    }
+
+    fn print_plain(&self) {
+        if let Some(ref g) = self.geometry {
+            println!("geometry: {},{} {}x{}", g.x, g.y, g.width, g.height);
+        }
+
+        for result in &self.albums {
+            print_plain_result("album.", result);
+        }
+
+        for result in &self.results {
+            print_plain_result("", result);
+        }
+
+        for file in &self.files {
+            if let Some(ref error) = file.error_message {
+                println!("{}.error: {}", file.path, error);
+            }
+
+            for result in &file.results {
+                print_plain_result(&format!("{}.", file.path), result);
+            }
+        }
+
+        // keep the output one line per field, even if the recognised text spans several lines
+        if let Some(ref text) = self.text {
+            println!("text: {}", text.trim().replace('\n', "\\n"));
+        }
+    }
+}
+
+fn print_plain_result(prefix: &str, result: &UploadResult) {
+    let fields = [
+        ("url", &result.url),
+        ("thumbnail_url", &result.thumbnail_url),
+        ("deletion_url", &result.deletion_url),
+        ("file_path", &result.file_path),
+        ("error", &result.error_message),
+    ];
+
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{}{}.{}: {}", prefix, result.uploader_name, name, value);
+        }
+    }
+
+    if let Some(ref response) = result.response {
+        println!(
+            "{}{}.status: {}",
+            prefix, result.uploader_name, response.status
+        );
+    }
+
+    if let Some(duration_ms) = result.duration_ms {
+        println!(
+            "{}{}.duration_ms: {}",
+            prefix, result.uploader_name, duration_ms
+        );
+    }
+}
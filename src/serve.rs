@@ -0,0 +1,689 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use delenix_lib::{
+    config::{self, Config, HttpUploader},
+    util,
+};
+use sha2::{Digest, Sha256};
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// Uploads bigger than this are refused, so a client can't fill up the disk in one request
+const MAX_UPLOAD_SIZE: u64 = 100 * 1024 * 1024;
+
+// How many requests are handled at the same time
+const WORKERS: usize = 4;
+
+// A worker stops after this many failures to receive a request in a row, rather than spinning on a broken listener
+const MAX_RECV_ERRORS: u64 = 10;
+
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    #[structopt(
+        long = "bind",
+        value_name = "ADDRESS",
+        default_value = "127.0.0.1:8080",
+        help = "Address to listen on"
+    )]
+    bind: String,
+
+    #[structopt(
+        long = "dir",
+        value_name = "DIR",
+        help = "Directory the uploads are stored in, defaults to ~/.local/share/delenix/uploads"
+    )]
+    dir: Option<String>,
+
+    #[structopt(
+        long = "token",
+        env = "DELENIX_SERVE_TOKEN",
+        hide_env_values = true,
+        help = "Token clients have to send as \"Authorization: Bearer TOKEN\" to upload"
+    )]
+    token: String,
+
+    #[structopt(
+        long = "public-url",
+        value_name = "URL",
+        help = "URL the server is reached at, used for the returned links, defaults to http://ADDRESS"
+    )]
+    public_url: Option<String>,
+
+    #[structopt(
+        long = "name",
+        value_name = "TEMPLATE",
+        default_value = "%r8",
+        help = "Template for the names of stored files, like the file_name of a File uploader"
+    )]
+    name: String,
+
+    #[structopt(
+        long = "emit",
+        value_name = "FORMAT",
+        possible_values = &["delenix", "sxcu"],
+        help = "Print an uploader config for this server instead of running it"
+    )]
+    emit: Option<String>,
+}
+
+impl Options {
+    fn public_url(&self) -> String {
+        self.public_url
+            .clone()
+            .unwrap_or_else(|| format!("http://{}", self.bind))
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    fn dir(&self) -> PathBuf {
+        PathBuf::from(
+            self.dir
+                .clone()
+                .unwrap_or_else(util::make_default_uploads_path),
+        )
+    }
+
+    // The uploader clients need to upload to this server
+    fn uploader(&self) -> HttpUploader {
+        let headers: HashMap<String, String> = [(
+            "Authorization".to_string(),
+            format!("Bearer {}", self.token),
+        )]
+        .into();
+
+        HttpUploader {
+            name: "delenix serve".to_string(),
+            destination_type: config::DestinationType::FileUploader,
+            request_method: "POST".to_string(),
+            request_url: format!("{}/upload", self.public_url()),
+            parameters: None,
+            headers: Some(headers),
+            body: config::Body::MultipartFormData,
+            arguments: None,
+            file_form_name: Some("file".to_string()),
+            url: "$json:url$".to_string(),
+            thumbnail_url: None,
+            deletion_url: Some("$json:deletion_url$".to_string()),
+            error_message: Some("$json:error$".to_string()),
+            response_script: None,
+            album: None,
+            payload: None,
+            encrypt: false,
+            expire: None,
+            deletion_method: None,
+            clipboard_template: None,
+        }
+    }
+}
+
+// Runs a small upload server that both delenix and ShareX can upload to:
+//
+//   POST /upload                 multipart (any part with a file name) or the raw file as the body,
+//                                responds with {"url", "deletion_url"} or {"error"}
+//   GET /NAME                    the stored file
+//   GET|DELETE /delete/NAME/KEY  deletes the file, the key is only handed out in the upload response
+pub fn run(conf: &Config, opts: &Options) -> i32 {
+    // an empty token would let anyone upload who sends "Authorization: Bearer "
+    if opts.token.trim().is_empty() {
+        tracing::error!("A token is required, pass --token or set DELENIX_SERVE_TOKEN");
+        return crate::output::EXIT_FAILURE;
+    }
+
+    if let Some(ref format) = opts.emit {
+        let uploader = opts.uploader();
+
+        let json = match format.as_str() {
+            "sxcu" => serde_json::to_string_pretty(&uploader.to_sharex()),
+            _ => serde_json::to_string_pretty(&config::Uploader::HTTP(uploader)),
+        };
+
+        return match json {
+            Ok(json) => {
+                println!("{}", json);
+                0
+            }
+            Err(e) => {
+                tracing::error!("Failed to serialize the uploader: {}", e);
+                crate::output::EXIT_FAILURE
+            }
+        };
+    }
+
+    if let Err(e) = std::fs::create_dir_all(opts.dir()) {
+        tracing::error!("Failed to create {}: {}", opts.dir().display(), e);
+        return crate::output::EXIT_FAILURE;
+    }
+
+    let server = match Server::http(&opts.bind) {
+        Ok(server) => server,
+        Err(e) => {
+            tracing::error!("Failed to listen on {}: {}", opts.bind, e);
+            return crate::output::EXIT_FAILURE;
+        }
+    };
+
+    tracing::info!(
+        "Serving {} on {}, uploads go to {}/upload",
+        opts.dir().display(),
+        opts.bind,
+        opts.public_url()
+    );
+
+    let failed = std::sync::atomic::AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        for _ in 0..WORKERS {
+            s.spawn(|| {
+                let mut errors = 0;
+
+                loop {
+                    let request = match server.recv() {
+                        Ok(request) => request,
+                        Err(e) if errors + 1 >= MAX_RECV_ERRORS => {
+                            tracing::error!("Giving up on receiving requests: {}", e);
+                            failed.store(true, std::sync::atomic::Ordering::Relaxed);
+                            break;
+                        }
+                        Err(e) => {
+                            // back off a little longer every time, so a broken listener doesn't flood the log
+                            errors += 1;
+                            tracing::error!("Failed to receive a request: {}", e);
+                            std::thread::sleep(std::time::Duration::from_secs(errors));
+                            continue;
+                        }
+                    };
+                    errors = 0;
+
+                    if let Err(e) = handle(conf, opts, request) {
+                        tracing::error!("Failed to respond: {}", e);
+                    }
+                }
+            });
+        }
+    });
+
+    if failed.into_inner() {
+        return crate::output::EXIT_FAILURE;
+    }
+
+    0
+}
+
+fn handle(conf: &Config, opts: &Options, mut request: Request) -> std::io::Result<()> {
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    let method = request.method().clone();
+
+    tracing::info!("{} {}", method, path);
+
+    match (method, segments.as_slice()) {
+        (Method::Post, ["upload"]) => {
+            let authorization = header(&request, "Authorization").unwrap_or_default();
+            if !constant_time_eq(
+                authorization.as_bytes(),
+                format!("Bearer {}", opts.token).as_bytes(),
+            ) {
+                return respond(request, json_error(401, "Invalid token"));
+            }
+
+            let response = match upload(conf, opts, &mut request) {
+                Ok(name) => {
+                    let body = serde_json::json!({
+                        "url": format!("{}/{}", opts.public_url(), name),
+                        "deletion_url": format!(
+                            "{}/delete/{}/{}",
+                            opts.public_url(),
+                            name,
+                            deletion_key(&opts.token, &name)
+                        ),
+                    });
+
+                    Response::from_string(body.to_string())
+                        .with_header(content_type("application/json"))
+                }
+                Err(e) => json_error(400, &e.to_string()),
+            };
+
+            respond(request, response)
+        }
+
+        (Method::Get | Method::Delete, ["delete", name, key]) => {
+            let name = name.to_string();
+            let path = match stored_file(opts, &name) {
+                Some(path) => path,
+                None => {
+                    return respond(
+                        request,
+                        Response::from_string("Not found").with_status_code(404),
+                    )
+                }
+            };
+
+            if !constant_time_eq(key.as_bytes(), deletion_key(&opts.token, &name).as_bytes()) {
+                return respond(
+                    request,
+                    Response::from_string("Invalid key").with_status_code(403),
+                );
+            }
+
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    tracing::info!("Deleted {}", name);
+                    respond(request, Response::from_string("Deleted"))
+                }
+                Err(e) => respond(
+                    request,
+                    Response::from_string(e.to_string()).with_status_code(500),
+                ),
+            }
+        }
+
+        (Method::Get, [name]) => {
+            let path = match stored_file(opts, name) {
+                Some(path) => path,
+                None => {
+                    return respond(
+                        request,
+                        Response::from_string("Not found").with_status_code(404),
+                    )
+                }
+            };
+
+            let format = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+            match std::fs::read(&path) {
+                Ok(data) => {
+                    let mime_type = util::mime_type(format);
+                    let mut response =
+                        Response::from_data(data).with_header(content_type(mime_type));
+
+                    // only media is shown in the browser, anything else could be a page or script in the
+                    // server's origin, so it's downloaded instead
+                    if !inline(mime_type) {
+                        response = response
+                            .with_header(response_header("Content-Disposition", "attachment"));
+                    }
+
+                    respond(request, response)
+                }
+                Err(e) => respond(
+                    request,
+                    Response::from_string(e.to_string()).with_status_code(500),
+                ),
+            }
+        }
+
+        _ => respond(
+            request,
+            Response::from_string("Not found").with_status_code(404),
+        ),
+    }
+}
+
+// Stores the uploaded file and returns the name it was stored under
+fn upload(
+    conf: &Config,
+    opts: &Options,
+    request: &mut Request,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let request_type = header(request, "Content-Type").unwrap_or_default();
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_UPLOAD_SIZE + 1)
+        .read_to_end(&mut body)?;
+
+    if body.len() as u64 > MAX_UPLOAD_SIZE {
+        return Err(format!("Uploads are limited to {} bytes", MAX_UPLOAD_SIZE).into());
+    }
+
+    let (data, format) = match boundary(&request_type) {
+        Some(boundary) => {
+            // prefer a part that says it's a file, delenix itself sends the file as a plain "file" field
+            let parts = parse_multipart(&body, &boundary);
+            let part = parts
+                .iter()
+                .find(|p| p.filename.is_some())
+                .or_else(|| parts.iter().find(|p| p.name.as_deref() == Some("file")))
+                .ok_or("No file in the upload")?;
+
+            let format = part
+                .filename
+                .as_deref()
+                .and_then(|f| Path::new(f).extension())
+                .and_then(|e| e.to_str())
+                .map(str::to_string)
+                .or_else(|| {
+                    part.content_type
+                        .as_deref()
+                        .and_then(util::extension)
+                        .map(str::to_string)
+                });
+
+            (part.data.to_vec(), format)
+        }
+        None => {
+            let format = util::extension(&request_type).map(str::to_string);
+            (body, format)
+        }
+    };
+
+    if data.is_empty() {
+        return Err("The upload is empty".into());
+    }
+
+    // the extension ends up in a path, so only allow something that looks like one
+    let format = format
+        .filter(|f| f.len() <= 8 && f.chars().all(|c| c.is_ascii_alphanumeric()))
+        .or_else(|| {
+            image::guess_format(&data)
+                .ok()
+                .and_then(|f| f.extensions_str().first())
+                .map(|e| e.to_string())
+        })
+        .unwrap_or_else(|| "bin".to_string())
+        .to_lowercase();
+
+    // templates without random parts could clash with an earlier upload, so retry a few times before giving up
+    for _ in 0..10 {
+        let name = format!("{}.{}", conf.make_filename(Some(&opts.name)), format);
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(opts.dir().join(&name));
+
+        match file {
+            Ok(mut file) => {
+                std::io::Write::write_all(&mut file, &data)?;
+                tracing::info!("Stored {} ({} bytes)", name, data.len());
+                return Ok(name);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err("Couldn't find a free file name, the name template needs more random characters".into())
+}
+
+// The file a request refers to, names that could point outside the directory are refused
+fn stored_file(opts: &Options, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return None;
+    }
+
+    let path = opts.dir().join(name);
+    path.is_file().then_some(path)
+}
+
+// Derived from the token, so the server doesn't have to remember the key it handed out for every file
+fn deletion_key(token: &str, name: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(format!("{}:{}", token, name)));
+    hash[..32].to_string()
+}
+
+// Compares secrets without returning early at the first difference, which would tell an attacker how much of
+// their guess was right. Only the length can be learned, and both secrets have a fixed one.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_string())
+}
+
+// Every response goes out through here, so uploaded files can never be sniffed into something else or run
+// script, whatever they turn out to be
+fn respond(request: Request, response: Response<std::io::Cursor<Vec<u8>>>) -> std::io::Result<()> {
+    request.respond(
+        response
+            .with_header(response_header("X-Content-Type-Options", "nosniff"))
+            .with_header(response_header("Content-Security-Policy", "sandbox")),
+    )
+}
+
+// Images and videos, except SVG which can carry script
+fn inline(mime_type: &str) -> bool {
+    (mime_type.starts_with("image/") || mime_type.starts_with("video/"))
+        && mime_type != "image/svg+xml"
+}
+
+fn response_header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn content_type(value: &str) -> Header {
+    response_header("Content-Type", value)
+}
+
+fn json_error(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(serde_json::json!({ "error": message }).to_string())
+        .with_status_code(status)
+        .with_header(content_type("application/json"))
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    if !content_type
+        .to_lowercase()
+        .starts_with("multipart/form-data")
+    {
+        return None;
+    }
+
+    content_type
+        .split(';')
+        .filter_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"').to_string())
+        .next()
+}
+
+struct Part<'a> {
+    name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: &'a [u8],
+}
+
+// A minimal multipart/form-data parser, which is all the uploads need since they're read into memory anyway
+fn parse_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<Part<'a>> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+
+    let mut pos = match find(body, delimiter.as_bytes(), 0) {
+        Some(pos) => pos + delimiter.len(),
+        None => return parts,
+    };
+
+    // every part starts with a CRLF after the delimiter, the last delimiter is followed by -- instead
+    while body[pos..].starts_with(b"\r\n") {
+        let start = pos + 2;
+
+        let headers_end = match find(body, b"\r\n\r\n", start) {
+            Some(end) => end,
+            None => break,
+        };
+        let next = match find(body, format!("\r\n{}", delimiter).as_bytes(), headers_end) {
+            Some(next) => next,
+            None => break,
+        };
+
+        let headers = String::from_utf8_lossy(&body[start..headers_end]);
+        let mut part = Part {
+            name: None,
+            filename: None,
+            content_type: None,
+            data: &body[headers_end + 4..next],
+        };
+
+        for line in headers.lines() {
+            let (name, value) = match line.split_once(':') {
+                Some(header) => header,
+                None => continue,
+            };
+
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                let param = |key: &str| {
+                    value
+                        .split(';')
+                        .filter_map(|p| p.trim().strip_prefix(key))
+                        .map(|v| v.trim_matches('"').to_string())
+                        .next()
+                };
+
+                part.name = param("name=");
+                part.filename = param("filename=");
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                part.content_type = Some(value.trim().to_string());
+            }
+        }
+
+        parts.push(part);
+        pos = next + 2 + delimiter.len();
+    }
+
+    parts
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(parts: &[&str]) -> Vec<u8> {
+        parts.concat().into_bytes()
+    }
+
+    #[test]
+    fn boundary_from_content_type() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("image/png; boundary=abc"), None);
+    }
+
+    #[test]
+    fn parses_parts() {
+        let body = body(&[
+            "preamble\r\n",
+            "--abc\r\n",
+            "Content-Disposition: form-data; name=\"title\"\r\n\r\n",
+            "hello\r\n",
+            "--abc\r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n",
+            "Content-Type: image/png\r\n\r\n",
+            "\r\n--ab not a delimiter\r\n",
+            "--abc--\r\n",
+        ]);
+
+        let parts = parse_multipart(&body, "abc");
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].data, b"hello");
+
+        assert_eq!(parts[1].name.as_deref(), Some("file"));
+        assert_eq!(parts[1].filename.as_deref(), Some("a.png"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("image/png"));
+        assert_eq!(parts[1].data, b"\r\n--ab not a delimiter");
+    }
+
+    #[test]
+    fn empty_part_data() {
+        let body = body(&[
+            "--abc\r\n",
+            "Content-Disposition: form-data; name=\"file\"\r\n\r\n",
+            "\r\n--abc--",
+        ]);
+
+        let parts = parse_multipart(&body, "abc");
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].data.is_empty());
+    }
+
+    #[test]
+    fn missing_parts() {
+        assert!(parse_multipart(b"", "abc").is_empty());
+        assert!(parse_multipart(b"no delimiter here", "abc").is_empty());
+        assert!(parse_multipart(b"--abc--\r\n", "abc").is_empty());
+        assert!(parse_multipart(&body(&["--other\r\n\r\ndata\r\n--other--"]), "abc").is_empty());
+    }
+
+    #[test]
+    fn truncated_body() {
+        let complete = [
+            "--abc\r\n",
+            "Content-Disposition: form-data; name=\"title\"\r\n\r\n",
+            "hello\r\n",
+        ];
+
+        // the file part never ends, so only the part before it is returned
+        let mut parts = complete.to_vec();
+        parts.extend([
+            "--abc\r\n",
+            "Content-Disposition: form-data; name=\"file\"\r\n\r\n",
+            "data that was cut off",
+        ]);
+        let body_without_end = body(&parts);
+        let parsed = parse_multipart(&body_without_end, "abc");
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].name.as_deref(), Some("title"));
+
+        // cut off in the middle of the headers
+        let mut parts = complete.to_vec();
+        parts.extend(["--abc\r\n", "Content-Disposition: form-da"]);
+        let body_in_headers = body(&parts);
+        assert_eq!(parse_multipart(&body_in_headers, "abc").len(), 1);
+
+        // cut off anywhere, including right after a delimiter, never panics
+        let full = body(&[
+            "--abc\r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\r\n",
+            "data\r\n--abc--\r\n",
+        ]);
+        for end in 0..full.len() {
+            assert!(parse_multipart(&full[..end], "abc").len() <= 1);
+        }
+        assert_eq!(parse_multipart(&full, "abc").len(), 1);
+    }
+
+    #[test]
+    fn only_media_is_inline() {
+        assert!(inline(util::mime_type("png")));
+        assert!(inline(util::mime_type("webm")));
+        assert!(!inline(util::mime_type("svg")));
+        assert!(!inline(util::mime_type("html")));
+        assert!(!inline(util::mime_type("pdf")));
+        assert!(!inline(util::mime_type("exe")));
+    }
+
+    #[test]
+    fn compares_secrets() {
+        assert!(constant_time_eq(b"Bearer token", b"Bearer token"));
+        assert!(!constant_time_eq(b"Bearer token", b"Bearer tokem"));
+        assert!(!constant_time_eq(b"Bearer ", b"Bearer token"));
+        assert!(!constant_time_eq(b"", b"Bearer token"));
+    }
+}